@@ -38,7 +38,7 @@ fn bench_big_merkle(c: &mut Criterion) {
             "Proof with width {}, arity {}, elements {}",
             WIDTH, MERKLE_ARITY, x
         );
-        let mut tree: BigMerkleTree<Scalar> = BigMerkleTree::new(path.as_str(), WIDTH).unwrap();
+        let mut tree: BigMerkleTree<Scalar> = BigMerkleTree::create(path.as_str(), WIDTH).unwrap();
         for i in 0..10 {
             tree.insert(i, Scalar::from(i as u64)).unwrap();
         }
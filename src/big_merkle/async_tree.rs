@@ -0,0 +1,172 @@
+use super::{BigMerkleTree, BigProof};
+use crate::{Error, PoseidonLeaf, Scalar};
+
+use std::ops;
+
+use tokio::task;
+
+/// Asynchronous facade of a [`BigMerkleTree`].
+///
+/// Every operation, storage and hashing included, is performed in the blocking pool of the tokio
+/// runtime, so the executor is never blocked. Every operation runs on its own handle of the
+/// tree, so the reads are performed concurrently, while the mutations are serialized by the lock
+/// of the tree. Concurrent tasks will always observe the root of a completed mutation.
+///
+/// The facade can be cloned, and every clone shares the same underlying tree.
+#[derive(Debug)]
+pub struct AsyncBigMerkleTree<T: PoseidonLeaf> {
+    tree: BigMerkleTree<T>,
+}
+
+impl<T: PoseidonLeaf> Clone for AsyncBigMerkleTree<T> {
+    fn clone(&self) -> Self {
+        AsyncBigMerkleTree {
+            tree: self.tree.clone(),
+        }
+    }
+}
+
+impl<T: PoseidonLeaf> From<BigMerkleTree<T>> for AsyncBigMerkleTree<T> {
+    fn from(tree: BigMerkleTree<T>) -> Self {
+        AsyncBigMerkleTree { tree }
+    }
+}
+
+impl<T: PoseidonLeaf + 'static> AsyncBigMerkleTree<T>
+where
+    Scalar: ops::Mul<T, Output = T>,
+{
+    /// Perform the provided operation over the tree in the blocking pool.
+    ///
+    /// Every mutation of the operation is atomic, and serialized with the mutations of the other
+    /// operations. A sequence of mutations may interleave with the ones of other operations, so
+    /// the dependent changes should be performed in a single [`BigMerkleTree::insert_batch`].
+    ///
+    /// The operation receives a dedicated handle of the tree, so the settings of the handle, such
+    /// as the number of threads, are discarded after the operation.
+    pub async fn execute<F, R>(&self, f: F) -> Result<R, Error>
+    where
+        F: FnOnce(&mut BigMerkleTree<T>) -> Result<R, Error> + Send + 'static,
+        R: Send + 'static,
+    {
+        let mut tree = self.tree.clone();
+
+        task::spawn_blocking(move || f(&mut tree))
+            .await
+            .map_err(|e| Error::Other(e.to_string()))?
+    }
+
+    /// Perform the provided read-only operation over the tree in the blocking pool.
+    ///
+    /// The operation is performed concurrently with the other operations of the facade. Every
+    /// call to the provided handle observes a single version of the tree; use a [`Snapshot`] to
+    /// extend this to a sequence of reads.
+    ///
+    /// [`Snapshot`]: crate::Snapshot
+    pub async fn read<F, R>(&self, f: F) -> Result<R, Error>
+    where
+        F: FnOnce(&BigMerkleTree<T>) -> Result<R, Error> + Send + 'static,
+        R: Send + 'static,
+    {
+        let tree = self.tree.clone();
+
+        task::spawn_blocking(move || f(&tree))
+            .await
+            .map_err(|e| Error::Other(e.to_string()))?
+    }
+
+    /// Insert the provided leaf on the provided index
+    pub async fn insert(&self, idx: usize, leaf: T) -> Result<(), Error> {
+        self.execute(move |t| t.insert(idx, leaf)).await
+    }
+
+    /// Insert a set of leaves, atomically
+    pub async fn insert_batch(&self, leaves: Vec<(usize, T)>) -> Result<(), Error> {
+        self.execute(move |t| t.insert_batch(leaves)).await
+    }
+
+    /// Set the provided leaf index as absent for the hash calculation
+    pub async fn remove(&self, idx: usize) -> Result<(), Error> {
+        self.execute(move |t| t.remove(idx)).await
+    }
+
+    /// Calculate and return the root of the merkle tree
+    pub async fn root(&self) -> Result<T, Error> {
+        self.read(|t| t.root()).await
+    }
+
+    /// Generate a proof of membership for the provided leaf index
+    pub async fn proof(&self, idx: usize) -> Result<BigProof<T>, Error> {
+        self.read(move |t| t.proof(idx)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    use std::sync::Arc;
+
+    use tokio::runtime::Builder;
+    use tokio::task;
+
+    #[test]
+    fn async_big_merkle() {
+        let mut rt = Builder::new().basic_scheduler().build().unwrap();
+
+        let t = BigMerkleTree::with_store(Arc::new(MemoryStore::default()), MERKLE_WIDTH).unwrap();
+        let tree = AsyncBigMerkleTree::from(t);
+        let mut reference = MerkleTree::<Scalar>::default();
+
+        for i in 0..16 {
+            reference.insert_unchecked(i, Scalar::from(i as u64));
+        }
+        reference.remove_unchecked(3);
+
+        rt.block_on(async {
+            let tasks: Vec<_> = (0..16)
+                .map(|i| {
+                    let tree = tree.clone();
+                    task::spawn(async move { tree.insert(i, Scalar::from(i as u64)).await })
+                })
+                .collect();
+
+            for t in tasks {
+                t.await.unwrap().unwrap();
+            }
+
+            tree.remove(3).await.unwrap();
+            let root = tree.root().await.unwrap();
+            assert_eq!(reference.root(), root);
+
+            let proof = tree.proof(7).await.unwrap();
+            assert!(proof.verify_at(7, &Scalar::from(7u64), &root));
+        });
+    }
+
+    #[test]
+    fn async_big_merkle_concurrent_reads() {
+        let mut rt = Builder::new().basic_scheduler().build().unwrap();
+
+        let t = BigMerkleTree::with_store(Arc::new(MemoryStore::default()), MERKLE_WIDTH).unwrap();
+        let tree = AsyncBigMerkleTree::from(t);
+
+        rt.block_on(async {
+            tree.insert(0, Scalar::one()).await.unwrap();
+            let root = tree.root().await.unwrap();
+
+            // The reads are not blocked by another reader of the tree
+            let snapshot = tree.tree.snapshot();
+            assert_eq!(root, tree.root().await.unwrap());
+            assert!(tree
+                .proof(0)
+                .await
+                .unwrap()
+                .verify_at(0, &Scalar::one(), &root));
+            drop(snapshot);
+
+            tree.insert(1, Scalar::one()).await.unwrap();
+            assert_ne!(root, tree.root().await.unwrap());
+        });
+    }
+}
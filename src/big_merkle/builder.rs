@@ -0,0 +1,316 @@
+use super::{BigMerkleTree, CacheConfig};
+use crate::{Error, MerkleStore, PoseidonLeaf, Scalar, MERKLE_ARITY};
+
+use std::fmt;
+use std::ops;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use rocksdb::{BlockBasedOptions, DBCompressionType, Options, DB};
+
+/// Height of a tree with the provided width, if the width is a power of the arity.
+///
+/// The height is calculated with integer arithmetic, so it is exact for every width.
+pub(crate) fn height_of(width: usize) -> Result<usize, Error> {
+    let mut height = 0;
+    let mut base = 1usize;
+
+    while base < width {
+        base = base
+            .checked_mul(MERKLE_ARITY)
+            .ok_or(Error::InvalidWidth(width))?;
+        height += 1;
+    }
+
+    if base == width && height > 0 {
+        Ok(height)
+    } else {
+        Err(Error::InvalidWidth(width))
+    }
+}
+
+/// Builder of a [`BigMerkleTree`] with validated configuration.
+///
+/// The tree is persisted in RocksDB, in the provided path, or in the provided storage backend.
+/// If a width is provided, the tree is created if the storage is empty; otherwise, the storage
+/// must already hold a tree.
+#[derive(Clone)]
+pub struct BigMerkleTreeBuilder {
+    path: Option<PathBuf>,
+    store: Option<Arc<dyn MerkleStore>>,
+    width: Option<usize>,
+    compression: DBCompressionType,
+    block_cache_size: Option<usize>,
+    bloom_filter_bits: Option<i32>,
+    create_if_missing: bool,
+    read_only: bool,
+    cache: CacheConfig,
+    threads: Option<usize>,
+    leaf_index: Option<bool>,
+    migrate: bool,
+}
+
+impl fmt::Debug for BigMerkleTreeBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("BigMerkleTreeBuilder")
+            .field("path", &self.path)
+            .field("store", &self.store)
+            .field("width", &self.width)
+            .field("compression", &self.compression)
+            .field("block_cache_size", &self.block_cache_size)
+            .field("bloom_filter_bits", &self.bloom_filter_bits)
+            .field("create_if_missing", &self.create_if_missing)
+            .field("read_only", &self.read_only)
+            .field("cache", &self.cache)
+            .field("threads", &self.threads)
+            .field("leaf_index", &self.leaf_index)
+            .field("migrate", &self.migrate)
+            .finish()
+    }
+}
+
+impl Default for BigMerkleTreeBuilder {
+    fn default() -> Self {
+        BigMerkleTreeBuilder {
+            path: None,
+            store: None,
+            width: None,
+            compression: DBCompressionType::Snappy,
+            block_cache_size: None,
+            bloom_filter_bits: None,
+            create_if_missing: true,
+            read_only: false,
+            cache: CacheConfig::default(),
+            threads: None,
+            leaf_index: None,
+            migrate: false,
+        }
+    }
+}
+
+impl BigMerkleTreeBuilder {
+    /// Persist the tree in a RocksDB in the provided path
+    pub fn path<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.path = Some(path.into());
+        self
+    }
+
+    /// Persist the tree in the provided storage backend. The RocksDB options are ignored.
+    pub fn store(mut self, store: Arc<dyn MerkleStore>) -> Self {
+        self.store = Some(store);
+        self
+    }
+
+    /// Width of the tree. Must be a power of `MERKLE_ARITY`.
+    pub fn width(mut self, width: usize) -> Self {
+        self.width = Some(width);
+        self
+    }
+
+    /// Compression of the RocksDB blocks. Defaults to Snappy.
+    pub fn compression(mut self, compression: DBCompressionType) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Size, in bytes, of the RocksDB LRU block cache
+    pub fn block_cache_size(mut self, size: usize) -> Self {
+        self.block_cache_size = Some(size);
+        self
+    }
+
+    /// Enable the RocksDB bloom filters with the provided number of bits per key
+    pub fn bloom_filter(mut self, bits_per_key: i32) -> Self {
+        self.bloom_filter_bits = Some(bits_per_key);
+        self
+    }
+
+    /// Create the RocksDB if it does not exist. Defaults to true.
+    pub fn create_if_missing(mut self, create_if_missing: bool) -> Self {
+        self.create_if_missing = create_if_missing;
+        self
+    }
+
+    /// Open the RocksDB in read-only mode. Every mutation of the tree will fail, and the
+    /// calculated nodes are cached only in memory, while the nodes already cached in the store
+    /// are still read.
+    pub fn read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
+    /// Configuration of the node caching
+    pub fn cache_config(mut self, cache: CacheConfig) -> Self {
+        self.cache = cache;
+        self
+    }
+
+    /// Number of workers used to calculate the root. Defaults to the number of CPUs.
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = Some(threads);
+        self
+    }
+
+    /// Maintain, or discard, the secondary index of the leaves by value. Defaults to the
+    /// persisted choice, or disabled for a new tree.
+    pub fn leaf_index(mut self, enabled: bool) -> Self {
+        self.leaf_index = Some(enabled);
+        self
+    }
+
+    /// Migrate a tree persisted with the legacy encoding of the coordinates before opening it.
+    /// Defaults to false.
+    pub fn migrate(mut self, migrate: bool) -> Self {
+        self.migrate = migrate;
+        self
+    }
+
+    fn options(&self) -> Options {
+        let mut opts = Options::default();
+        opts.create_if_missing(self.create_if_missing);
+        opts.set_compression_type(self.compression);
+
+        if self.block_cache_size.is_some() || self.bloom_filter_bits.is_some() {
+            let mut block = BlockBasedOptions::default();
+
+            if let Some(size) = self.block_cache_size {
+                block.set_lru_cache(size);
+            }
+
+            if let Some(bits) = self.bloom_filter_bits {
+                block.set_bloom_filter(bits, false);
+            }
+
+            opts.set_block_based_table_factory(&block);
+        }
+
+        opts
+    }
+
+    /// Validate the configuration, and create or restore the tree
+    pub fn build<T: PoseidonLeaf>(self) -> Result<BigMerkleTree<T>, Error>
+    where
+        Scalar: ops::Mul<T, Output = T>,
+    {
+        if let Some(width) = self.width {
+            height_of(width)?;
+        }
+
+        let db: Arc<dyn MerkleStore> = match (&self.store, &self.path) {
+            (Some(store), _) => Arc::clone(store),
+            (None, Some(path)) if self.read_only => {
+                let db = DB::open_for_read_only(&self.options(), path, false)
+                    .map_err(|e| Error::Other(e.to_string()))?;
+                Arc::new(db)
+            }
+            (None, Some(path)) => {
+                let db =
+                    DB::open(&self.options(), path).map_err(|e| Error::Other(e.to_string()))?;
+                Arc::new(db)
+            }
+            (None, None) => {
+                return Err(Error::Other(
+                    "A path or a store must be provided to build the tree.".to_owned(),
+                ))
+            }
+        };
+
+        if self.migrate {
+            BigMerkleTree::<T>::migrate_store(db.as_ref())?;
+        }
+
+        let mut tree = match self.width {
+            Some(width) => BigMerkleTree::with_store(db, width)?,
+            None => BigMerkleTree::open_store(db)?,
+        };
+
+        // The nodes cannot be persisted in a read-only store
+        let mut cache = self.cache;
+        if self.read_only {
+            cache.disk_persist = false;
+        }
+
+        tree.set_cache_config(cache);
+        if let Some(threads) = self.threads {
+            tree.set_threads(threads);
+        }
+        if let Some(enabled) = self.leaf_index {
+            tree.set_leaf_index(enabled)?;
+        }
+
+        Ok(tree)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::height_of;
+    use crate::*;
+
+    use rocksdb::DBCompressionType;
+    use tempdir::TempDir;
+
+    #[test]
+    fn big_merkle_builder() {
+        let mut width = MERKLE_ARITY;
+        for height in 1.. {
+            assert_eq!(height, height_of(width).unwrap());
+
+            match width.checked_mul(MERKLE_ARITY) {
+                Some(w) => width = w,
+                None => break,
+            }
+        }
+
+        for width in vec![0, 1, MERKLE_ARITY + 1, MERKLE_WIDTH - 1, usize::max_value()] {
+            match BigMerkleTreeBuilder::default()
+                .width(width)
+                .build::<Scalar>()
+            {
+                Err(Error::InvalidWidth(w)) => assert_eq!(width, w),
+                _ => panic!("The invalid width {} was accepted", width),
+            }
+        }
+
+        let db_path = TempDir::new("big_merkle_builder")
+            .map(|t| t.into_path())
+            .unwrap();
+        assert!(BigMerkleTreeBuilder::default()
+            .path(db_path.join("missing"))
+            .create_if_missing(false)
+            .width(MERKLE_WIDTH)
+            .build::<Scalar>()
+            .is_err());
+
+        let root = {
+            let mut t = BigMerkleTreeBuilder::default()
+                .path(&db_path)
+                .width(MERKLE_WIDTH)
+                .compression(DBCompressionType::Lz4)
+                .block_cache_size(1 << 20)
+                .bloom_filter(10)
+                .threads(2)
+                .build::<Scalar>()
+                .unwrap();
+
+            assert_eq!(MERKLE_HEIGHT, t.height());
+            assert_eq!(2, t.threads());
+            t.insert(3, Scalar::one()).unwrap();
+            t.root().unwrap()
+        };
+
+        let mut t = BigMerkleTreeBuilder::default()
+            .path(&db_path)
+            .read_only(true)
+            .build::<Scalar>()
+            .unwrap();
+        assert_eq!(MERKLE_WIDTH, t.width());
+        assert_eq!(root, t.root().unwrap());
+        assert!(t.insert(4, Scalar::one()).is_err());
+
+        // The nodes cached in the store are still read
+        let config = t.cache_config();
+        assert!(!config.disk_persist);
+        assert_eq!(CacheConfig::default().disk_interval, config.disk_interval);
+    }
+}
@@ -0,0 +1,288 @@
+use super::IntervalSet;
+use crate::store::fetch_raw;
+use crate::{Error, IterDirection, MerkleStore, StoreBatch};
+
+use std::collections::VecDeque;
+use std::convert::TryInto;
+
+use serde::{Deserialize, Serialize};
+
+/// Key of the persisted list of checkpoints. It cannot collide with the serialized coordinates.
+const CHECKPOINTS_KEY: &[u8] = b"checkpoints";
+
+/// Prefix of the key of every persisted checkpoint
+const CHECKPOINT_PREFIX: &[u8] = b"checkpoint-";
+
+/// Prefix of the previous leaves recorded by the checkpoints, followed by the big-endian id and
+/// index, so the leaves of a checkpoint are contiguous.
+const LEAF_PREFIX: &[u8] = b"cl";
+
+/// Default number of checkpoints retained by a tree
+pub(crate) const CHECKPOINT_CAPACITY: usize = 16;
+
+/// Identifier of a checkpoint of a [`BigMerkleTree`]
+///
+/// [`BigMerkleTree`]: crate::BigMerkleTree
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct CheckpointId(pub u64);
+
+/// Retained checkpoints of a tree, from the oldest to the latest
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct Checkpoints {
+    pub capacity: usize,
+    pub next: u64,
+    pub ids: VecDeque<u64>,
+}
+
+impl Default for Checkpoints {
+    fn default() -> Self {
+        Checkpoints {
+            capacity: CHECKPOINT_CAPACITY,
+            next: 0,
+            ids: VecDeque::new(),
+        }
+    }
+}
+
+impl Checkpoints {
+    /// Fetch the checkpoints persisted in the store, if any
+    pub fn fetch(db: &dyn MerkleStore) -> Result<Self, Error> {
+        fetch_raw(db, CHECKPOINTS_KEY).map(|c| c.unwrap_or_default())
+    }
+
+    /// Append the persistence of the checkpoints to the provided batch
+    pub fn batch(&self, batch: &mut StoreBatch) -> Result<(), Error> {
+        let checkpoints = bincode::serialize(self).map_err(|e| Error::Other(e.to_string()))?;
+        batch.put(CHECKPOINTS_KEY, checkpoints);
+
+        Ok(())
+    }
+
+    /// Drop the oldest checkpoints that exceed the capacity, appending the deletion of their
+    /// records to the provided batch
+    pub fn truncate(&mut self, db: &dyn MerkleStore, batch: &mut StoreBatch) -> Result<(), Error> {
+        while self.ids.len() > self.capacity {
+            if let Some(id) = self.ids.pop_front() {
+                Checkpoint::batch_delete(db, id, batch)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// State of the tree when the checkpoint was created.
+///
+/// The previous value of every leaf modified since then is persisted under its own key, so a
+/// mutation writes only the leaves it modifies.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct Checkpoint {
+    pub id: u64,
+    pub empty_intervals: IntervalSet,
+    /// Serialized history of roots
+    pub roots: Vec<u8>,
+}
+
+impl Checkpoint {
+    /// Checkpoint of the tree with the provided empty intervals and serialized history of roots,
+    /// with no modified leaves
+    pub fn new(id: u64, empty_intervals: IntervalSet, roots: Vec<u8>) -> Self {
+        Checkpoint {
+            id,
+            empty_intervals,
+            roots,
+        }
+    }
+
+    /// Key of the persisted checkpoint with the provided id
+    pub fn key(id: u64) -> Vec<u8> {
+        let mut key = CHECKPOINT_PREFIX.to_vec();
+        key.extend_from_slice(&id.to_be_bytes());
+        key
+    }
+
+    /// Fetch the persisted checkpoint with the provided id
+    pub fn fetch(db: &dyn MerkleStore, id: u64) -> Result<Self, Error> {
+        fetch_raw(db, Checkpoint::key(id).as_slice())?.ok_or(Error::CheckpointNotFound(id))
+    }
+
+    /// Append the persistence of the checkpoint to the provided batch
+    pub fn batch(&self, batch: &mut StoreBatch) -> Result<(), Error> {
+        let checkpoint = bincode::serialize(self).map_err(|e| Error::Other(e.to_string()))?;
+        batch.put(Checkpoint::key(self.id), checkpoint);
+
+        Ok(())
+    }
+
+    /// Key of the previous leaf of the provided index, recorded by the checkpoint with the
+    /// provided id
+    fn leaf_key(id: u64, idx: usize) -> Vec<u8> {
+        let mut key = LEAF_PREFIX.to_vec();
+        key.extend_from_slice(&id.to_be_bytes());
+        key.extend_from_slice(&(idx as u64).to_be_bytes());
+        key
+    }
+
+    /// Check if the checkpoint recorded the previous leaf of the provided index
+    pub fn is_recorded(&self, db: &dyn MerkleStore, idx: usize) -> Result<bool, Error> {
+        db.get(Checkpoint::leaf_key(self.id, idx).as_slice())
+            .map(|l| l.is_some())
+    }
+
+    /// Append the persistence of the previous serialized leaf of the provided index to the
+    /// provided batch
+    pub fn batch_leaf(
+        &self,
+        idx: usize,
+        leaf: Option<Vec<u8>>,
+        batch: &mut StoreBatch,
+    ) -> Result<(), Error> {
+        let leaf = bincode::serialize(&leaf).map_err(|e| Error::Other(e.to_string()))?;
+        batch.put(Checkpoint::leaf_key(self.id, idx), leaf);
+
+        Ok(())
+    }
+
+    /// Iterate over the previous leaves recorded by the checkpoint with the provided id, as the
+    /// index and the previous serialized leaf, in ascending order of index.
+    pub fn leaves<'a>(
+        db: &'a dyn MerkleStore,
+        id: u64,
+    ) -> impl Iterator<Item = Result<(usize, Option<Vec<u8>>), Error>> + 'a {
+        let prefix = Checkpoint::leaf_key(id, 0);
+        let prefix_len = LEAF_PREFIX.len() + 8;
+
+        db.iter_from(prefix.as_slice(), IterDirection::Forward)
+            .take_while(move |item| match item {
+                Ok((k, _)) => k.starts_with(&prefix[..prefix_len]),
+                Err(_) => true,
+            })
+            .map(move |item| {
+                let (k, v) = item?;
+                let idx = k
+                    .get(prefix_len..)
+                    .and_then(|i| i.try_into().ok())
+                    .map(u64::from_be_bytes)
+                    .ok_or(Error::InvalidLength)?;
+                let leaf =
+                    bincode::deserialize(v.as_slice()).map_err(|e| Error::Other(e.to_string()))?;
+
+                Ok((idx as usize, leaf))
+            })
+    }
+
+    /// Append the deletion of the checkpoint with the provided id, and of its recorded leaves, to
+    /// the provided batch
+    pub fn batch_delete(
+        db: &dyn MerkleStore,
+        id: u64,
+        batch: &mut StoreBatch,
+    ) -> Result<(), Error> {
+        let prefix = Checkpoint::leaf_key(id, 0);
+
+        for item in db.iter_prefix(&prefix[..LEAF_PREFIX.len() + 8]) {
+            batch.delete(item?.0);
+        }
+        batch.delete(Checkpoint::key(id));
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{big_merkle_small, InterruptedStore};
+    use crate::*;
+
+    use std::sync::atomic::Ordering;
+    use std::sync::Arc;
+
+    #[test]
+    fn big_merkle_checkpoints() {
+        for mut t in big_merkle_small("big_merkle_checkpoints") {
+            let mut reference = MerkleTree::<Scalar>::default();
+
+            for i in 0..10 {
+                t.insert(i, Scalar::from(i as u64)).unwrap();
+                reference.insert_unchecked(i, Scalar::from(i as u64));
+            }
+            let c1 = t.checkpoint().unwrap();
+            let (r1, s1) = (reference.root(), t.size());
+
+            for i in 10..20 {
+                t.insert(i, Scalar::from(i as u64)).unwrap();
+            }
+            t.remove(3).unwrap();
+            t.insert(5, Scalar::zero()).unwrap();
+            let c2 = t.checkpoint().unwrap();
+            let r2 = t.root().unwrap();
+
+            t.remove(15).unwrap();
+            t.insert(5, Scalar::one()).unwrap();
+            t.insert(40, Scalar::one()).unwrap();
+            assert_ne!(r2, t.root().unwrap());
+
+            // The roots recorded after the checkpoint are discarded by the rollback
+            let abandoned = t.record_root(1).unwrap();
+            assert!(t.is_known_root(&abandoned).unwrap());
+
+            t.rollback_to(c2).unwrap();
+            assert_eq!(r2, t.root().unwrap());
+            assert_eq!(20, t.size());
+            assert!(!t.is_known_root(&abandoned).unwrap());
+
+            // The rollback is persisted
+            let mut t = BigMerkleTree::<Scalar>::open_store(Arc::clone(&t.db)).unwrap();
+            assert_eq!(vec![c1, c2], t.checkpoints());
+            t.insert(30, Scalar::one()).unwrap();
+
+            t.rollback_to(c1).unwrap();
+            assert_eq!(r1, t.root().unwrap());
+            assert_eq!(s1, t.size());
+            assert!(!t.node_is_empty(t.height(), 3));
+            assert!(t.node_is_empty(t.height(), 15));
+
+            match t.rollback_to(c2) {
+                Err(Error::CheckpointNotFound(_)) => (),
+                _ => panic!("The checkpoint was not dropped by the rollback"),
+            }
+
+            // Only the latest checkpoints are retained
+            t.set_checkpoint_capacity(2).unwrap();
+            let c3 = t.checkpoint().unwrap();
+            let c4 = t.checkpoint().unwrap();
+            assert_eq!(vec![c3, c4], t.checkpoints());
+            assert!(t.rollback_to(c1).is_err());
+        }
+    }
+
+    #[test]
+    fn big_merkle_checkpoint_writes() {
+        let store = Arc::new(InterruptedStore::default());
+        let mut t = BigMerkleTree::with_store(store.clone(), MERKLE_WIDTH).unwrap();
+
+        let c = t.checkpoint().unwrap();
+        let root = t.root().unwrap();
+
+        // Only the newly recorded leaves are written, regardless of the recorded ones
+        t.insert(0, Scalar::one()).unwrap();
+        t.insert(1, Scalar::one()).unwrap();
+        let written = store.written.load(Ordering::SeqCst);
+
+        for i in 2..40 {
+            t.insert(i, Scalar::one()).unwrap();
+        }
+        t.insert(0, Scalar::zero()).unwrap();
+        t.insert(40, Scalar::one()).unwrap();
+        assert_eq!(written, store.written.load(Ordering::SeqCst));
+
+        let mut t = BigMerkleTree::<Scalar>::open_store(store.clone()).unwrap();
+        t.rollback_to(c).unwrap();
+        assert_eq!(root, t.root().unwrap());
+
+        // The dropped checkpoints discard their recorded leaves
+        t.insert(0, Scalar::one()).unwrap();
+        t.set_checkpoint_capacity(0).unwrap();
+        assert_eq!(0, store.iter_prefix(b"cl").count());
+    }
+}
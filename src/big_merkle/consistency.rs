@@ -0,0 +1,273 @@
+use super::empty_nodes;
+use crate::proof::{path_index, path_matches};
+use crate::{Poseidon, PoseidonLeaf, Scalar, VerifyError, MERKLE_ARITY};
+
+use std::cmp;
+use std::ops;
+
+/// Proof that a tree of `new_size` leaves only appended leaves to a tree of `old_size` leaves.
+///
+/// The size of a tree is the number of leaves up to the last present one. The proof is composed
+/// by the path of the last leaf of each size in the new tree.
+///
+/// Every node on the left of the path of the last old leaf is the root of a complete sub-tree,
+/// and was not affected by the appended leaves. Replacing the nodes on the right of that path
+/// with empty sub-trees will then reconstruct the old root, while the path itself reconstructs
+/// the new root.
+///
+/// Every node on the right of the path of the last new leaf must be an empty sub-tree.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConsistencyProof<T: PoseidonLeaf> {
+    old_path: Vec<(usize, [Option<T>; MERKLE_ARITY])>,
+    new_path: Vec<(usize, [Option<T>; MERKLE_ARITY])>,
+}
+
+impl<T: PoseidonLeaf> ConsistencyProof<T> {
+    pub(crate) fn new(
+        old_path: Vec<(usize, [Option<T>; MERKLE_ARITY])>,
+        new_path: Vec<(usize, [Option<T>; MERKLE_ARITY])>,
+    ) -> Self {
+        ConsistencyProof { old_path, new_path }
+    }
+
+    /// Path of the last leaf of the old tree, starting from the base of the tree
+    pub fn old_path(&self) -> &Vec<(usize, [Option<T>; MERKLE_ARITY])> {
+        &self.old_path
+    }
+
+    /// Path of the last leaf of the new tree, starting from the base of the tree
+    pub fn new_path(&self) -> &Vec<(usize, [Option<T>; MERKLE_ARITY])> {
+        &self.new_path
+    }
+
+    /// Verify if the tree with the new root was created only by appending leaves to the tree
+    /// with the old root.
+    pub fn verify(&self, old_size: usize, old_root: &T, new_size: usize, new_root: &T) -> bool
+    where
+        Scalar: ops::Mul<T, Output = T>,
+    {
+        self.verify_detailed(old_size, old_root, new_size, new_root)
+            .is_ok()
+    }
+
+    /// Same as [`ConsistencyProof::verify`], but reporting the reason of a failure
+    pub fn verify_detailed(
+        &self,
+        old_size: usize,
+        old_root: &T,
+        new_size: usize,
+        new_root: &T,
+    ) -> Result<(), VerifyError>
+    where
+        Scalar: ops::Mul<T, Output = T>,
+    {
+        if old_size > new_size {
+            return Err(VerifyError::SizeMismatch {
+                old: old_size,
+                new: new_size,
+            });
+        }
+
+        // Without leaves, there is no path to define the height of the tree
+        if new_size == 0 {
+            if !self.old_path.is_empty() || !self.new_path.is_empty() {
+                return Err(VerifyError::WrongHeight {
+                    expected: 0,
+                    found: cmp::max(self.old_path.len(), self.new_path.len()),
+                });
+            }
+
+            if old_root != new_root {
+                return Err(VerifyError::RootMismatch);
+            }
+
+            return Ok(());
+        }
+
+        let height = self.new_path.len();
+        let expected_old = if old_size == 0 { 0 } else { height };
+        if self.old_path.len() != expected_old {
+            return Err(VerifyError::WrongHeight {
+                expected: expected_old,
+                found: self.old_path.len(),
+            });
+        }
+
+        let empty = empty_nodes(height);
+
+        // Every node on the right of the new path must be empty
+        let (root, not_empty) = walk(&self.new_path, new_size - 1, &empty)?;
+        if let Some(level) = not_empty {
+            return Err(VerifyError::BadSibling { level });
+        }
+        if root != Some(*new_root) {
+            return Err(VerifyError::RootMismatch);
+        }
+
+        let old = if old_size == 0 {
+            empty[height]
+        } else {
+            let (root, _) = walk(&self.old_path, old_size - 1, &empty)?;
+            if root != Some(*new_root) {
+                return Err(VerifyError::RootMismatch);
+            }
+
+            old_root_of(&self.old_path, &empty)
+        };
+
+        if old != Some(*old_root) {
+            return Err(VerifyError::RootMismatch);
+        }
+
+        Ok(())
+    }
+}
+
+/// Calculate the root reconstructed by the path of the provided leaf index.
+///
+/// Will also return the first level that contains a non-empty node on the right of the path, if
+/// any.
+fn walk<T: PoseidonLeaf>(
+    path: &[(usize, [Option<T>; MERKLE_ARITY])],
+    index: usize,
+    empty: &[Option<T>],
+) -> Result<(Option<T>, Option<usize>), VerifyError>
+where
+    Scalar: ops::Mul<T, Output = T>,
+{
+    if let Some((level, (idx, _))) = path
+        .iter()
+        .enumerate()
+        .find(|(_, (idx, _))| *idx >= MERKLE_ARITY)
+    {
+        return Err(VerifyError::IndexOutOfRange { level, idx: *idx });
+    }
+
+    if !path_matches(path.iter().map(|(idx, _)| *idx), index) {
+        return Err(VerifyError::IndexMismatch {
+            expected: index,
+            found: path_index(path.iter().map(|(idx, _)| *idx)),
+        });
+    }
+
+    let mut h = Poseidon::default();
+    let mut node = path.first().and_then(|(idx, data)| data[*idx]);
+    let mut not_empty = None;
+
+    for (level, (idx, data)) in path.iter().enumerate() {
+        if not_empty.is_none() && data[idx + 1..].iter().any(|n| n != &empty[level]) {
+            not_empty = Some(level);
+        }
+
+        let mut data = *data;
+        data[*idx] = node;
+
+        h.replace(&data);
+        node = Some(h.hash());
+    }
+
+    Ok((node, not_empty))
+}
+
+/// Calculate the root of the tree if every node on the right of the path was empty
+fn old_root_of<T: PoseidonLeaf>(
+    path: &[(usize, [Option<T>; MERKLE_ARITY])],
+    empty: &[Option<T>],
+) -> Option<T>
+where
+    Scalar: ops::Mul<T, Output = T>,
+{
+    let mut h = Poseidon::default();
+    let mut node = path.first().and_then(|(idx, data)| data[*idx]);
+
+    for (level, (idx, data)) in path.iter().enumerate() {
+        let mut data = *data;
+        data[*idx] = node;
+        data[idx + 1..].iter_mut().for_each(|n| *n = empty[level]);
+
+        h.replace(&data);
+        node = Some(h.hash());
+    }
+
+    node
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::big_merkle_stores;
+    use crate::*;
+
+    #[test]
+    fn consistency_proof() {
+        for mut t in big_merkle_stores("consistency_proof") {
+            let mut roots = vec![t.root().unwrap()];
+
+            for i in 0..40 {
+                t.insert(i, Scalar::from(i as u64 + 1)).unwrap();
+                roots.push(t.root().unwrap());
+            }
+
+            let new_size = t.size();
+            assert_eq!(40, new_size);
+
+            for old_size in vec![0, 1, 3, 4, 16, 17, 39, 40] {
+                let proof = t.consistency_proof(old_size).unwrap();
+                assert_eq!(
+                    Ok(()),
+                    proof.verify_detailed(old_size, &roots[old_size], new_size, &roots[new_size])
+                );
+
+                // The sizes are bound to the roots
+                assert!(!proof.verify(old_size, &roots[old_size], new_size - 1, &roots[new_size]));
+                if old_size > 0 {
+                    assert!(!proof.verify(
+                        old_size - 1,
+                        &roots[old_size],
+                        new_size,
+                        &roots[new_size]
+                    ));
+                    assert!(!proof.verify(
+                        old_size,
+                        &roots[old_size - 1],
+                        new_size,
+                        &roots[new_size]
+                    ));
+                }
+            }
+
+            assert!(t.consistency_proof(new_size + 1).is_err());
+
+            // The tree cannot shrink
+            let proof = t.consistency_proof(new_size).unwrap();
+            assert_eq!(
+                Err(VerifyError::SizeMismatch {
+                    old: new_size + 1,
+                    new: new_size
+                }),
+                proof.verify_detailed(new_size + 1, &roots[new_size], new_size, &roots[new_size])
+            );
+        }
+    }
+
+    #[test]
+    fn consistency_proof_rewrite() {
+        for mut t in big_merkle_stores("consistency_proof_rewrite") {
+            for i in 0..20 {
+                t.insert(i, Scalar::from(i as u64 + 1)).unwrap();
+            }
+
+            let old_root: Scalar = t.root().unwrap();
+
+            // Rewriting an existing leaf is not an append
+            t.insert(3, Scalar::zero()).unwrap();
+            t.insert(20, Scalar::one()).unwrap();
+            let new_root: Scalar = t.root().unwrap();
+
+            let proof = t.consistency_proof(20).unwrap();
+            assert_eq!(
+                Err(VerifyError::RootMismatch),
+                proof.verify_detailed(20, &old_root, 21, &new_root)
+            );
+        }
+    }
+}
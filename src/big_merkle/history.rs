@@ -0,0 +1,162 @@
+use crate::store::fetch_raw;
+use crate::{Error, IterDirection, MerkleStore, StoreBatch};
+
+use std::convert::TryInto;
+
+use serde::{Deserialize, Serialize};
+
+/// Key of the persisted history state. It cannot collide with the serialized coordinates.
+const HISTORY_KEY: &[u8] = b"history";
+
+/// Prefix of the log of leaf changes, followed by the big-endian version and index, so the log
+/// is ordered by version.
+const LOG_PREFIX: &[u8] = b"hv";
+
+/// Version of a tree, and the range of versions retained by its history
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub(crate) struct History {
+    /// Incremented by every mutation of the tree
+    pub version: u64,
+    /// Number of past versions retained, if the history is enabled
+    pub horizon: Option<u64>,
+    /// Version in which the history was enabled
+    pub since: u64,
+}
+
+impl History {
+    /// Fetch the history state persisted in the store, if any
+    pub fn fetch(db: &dyn MerkleStore) -> Result<Self, Error> {
+        fetch_raw(db, HISTORY_KEY).map(|h| h.unwrap_or_default())
+    }
+
+    /// Append the persistence of the history state to the provided batch
+    pub fn batch(&self, batch: &mut StoreBatch) -> Result<(), Error> {
+        let history = bincode::serialize(self).map_err(|e| Error::Other(e.to_string()))?;
+        batch.put(HISTORY_KEY, history);
+
+        Ok(())
+    }
+
+    /// Oldest version that can be restored, if the history is enabled
+    pub fn oldest(&self) -> Option<u64> {
+        self.horizon
+            .map(|h| self.since.max(self.version.saturating_sub(h)))
+    }
+
+    /// Check if the provided version can be restored
+    pub fn retains(&self, version: u64) -> bool {
+        self.oldest()
+            .map(|o| o <= version && version <= self.version)
+            .unwrap_or(false)
+    }
+
+    /// Key of the log entry of a leaf changed in the provided version
+    pub fn log_key(version: u64, idx: usize) -> Vec<u8> {
+        let mut key = LOG_PREFIX.to_vec();
+        key.extend_from_slice(&version.to_be_bytes());
+        key.extend_from_slice(&(idx as u64).to_be_bytes());
+        key
+    }
+
+    /// Decode the version and index of a log entry key
+    fn parse_key(key: &[u8]) -> Option<(u64, usize)> {
+        let key = key.get(LOG_PREFIX.len()..)?;
+        let version = key.get(..8)?.try_into().ok().map(u64::from_be_bytes)?;
+        let idx = key.get(8..16)?.try_into().ok().map(u64::from_be_bytes)?;
+
+        Some((version, idx as usize))
+    }
+
+    /// Iterate over the log entries of the changes performed after the provided version, as the
+    /// changed version, index, and the previous serialized leaf.
+    pub fn changes_after<'a>(
+        db: &'a dyn MerkleStore,
+        version: u64,
+    ) -> impl Iterator<Item = Result<(u64, usize, Option<Vec<u8>>), Error>> + 'a {
+        let from = History::log_key(version.saturating_add(1), 0);
+
+        db.iter_from(from.as_slice(), IterDirection::Forward)
+            .take_while(|item| match item {
+                Ok((k, _)) => k.starts_with(LOG_PREFIX),
+                Err(_) => true,
+            })
+            .map(|item| {
+                let (k, v) = item?;
+                let (version, idx) =
+                    History::parse_key(k.as_slice()).ok_or(Error::InvalidLength)?;
+                let leaf =
+                    bincode::deserialize(v.as_slice()).map_err(|e| Error::Other(e.to_string()))?;
+
+                Ok((version, idx, leaf))
+            })
+    }
+
+    /// Append to the provided batch the deletion of the log entries that are not required to
+    /// restore the retained versions
+    pub fn prune(&self, db: &dyn MerkleStore, batch: &mut StoreBatch) -> Result<(), Error> {
+        // The changes up to the oldest retained version are already applied to it
+        let oldest = self.oldest();
+
+        for item in db.iter_prefix(LOG_PREFIX) {
+            let (k, _) = item?;
+
+            match (oldest, History::parse_key(k.as_slice())) {
+                (Some(o), Some((version, _))) if version > o => break,
+                (Some(_), None) => break,
+                _ => batch.delete(k),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::big_merkle_small;
+    use crate::*;
+
+    use std::sync::Arc;
+
+    #[test]
+    fn big_merkle_history() {
+        for mut t in big_merkle_small("big_merkle_history") {
+            assert!(t.root_at(t.version()).is_err());
+            t.set_history_horizon(Some(3)).unwrap();
+
+            for i in 0..10 {
+                t.insert(i, Scalar::from(i as u64)).unwrap();
+            }
+            let (v1, r1) = (t.version(), t.root().unwrap());
+
+            t.insert(2, Scalar::from(100u64)).unwrap();
+            t.remove(5).unwrap();
+            let (v2, r2) = (t.version(), t.root().unwrap());
+
+            t.insert(20, Scalar::one()).unwrap();
+            let r3 = t.root().unwrap();
+
+            assert_eq!(Some(v1), t.oldest_version());
+            assert_eq!(r1, t.root_at(v1).unwrap());
+            assert_eq!(r2, t.root_at(v2).unwrap());
+            assert_eq!(r3, t.root_at(t.version()).unwrap());
+            assert_eq!(r3, t.root().unwrap());
+
+            let proof = t.proof_at(v1, 2).unwrap();
+            assert!(proof.verify_at(2, &Scalar::from(2u64), &r1));
+            let proof = t.proof_at(v1, 5).unwrap();
+            assert!(proof.verify_at(5, &Scalar::from(5u64), &r1));
+            let proof = t.proof_at(v2, 2).unwrap();
+            assert!(proof.verify_at(2, &Scalar::from(100u64), &r2));
+
+            // The versions beyond the horizon are pruned
+            let mut t = BigMerkleTree::<Scalar>::open_store(Arc::clone(&t.db)).unwrap();
+            t.insert(21, Scalar::one()).unwrap();
+            match t.root_at(v1) {
+                Err(Error::VersionNotRetained(v)) => assert_eq!(v1, v),
+                _ => panic!("The version beyond the horizon was restored"),
+            }
+            assert_eq!(r2, t.root_at(v2).unwrap());
+        }
+    }
+}
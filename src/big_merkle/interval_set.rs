@@ -0,0 +1,315 @@
+use std::collections::BTreeMap;
+use std::iter::FromIterator;
+use std::ops::Range;
+
+use serde::{Deserialize, Serialize};
+
+/// Previous end of every start modified by an update of an [`IntervalSet`], in the order of the
+/// modifications. An absent end represents a start that was not in the set.
+pub(crate) type IntervalChanges = Vec<(usize, Option<usize>)>;
+
+/// Ordered set of disjoint, non-adjacent intervals of the base of the tree.
+///
+/// The intervals are indexed by their start, so containment and updates cost `O(log n)` on the
+/// number of intervals, plus the number of intervals touched by the update.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+pub(crate) struct IntervalSet {
+    /// Map of `start -> end` for every interval `start..end`
+    intervals: BTreeMap<usize, usize>,
+}
+
+impl FromIterator<Range<usize>> for IntervalSet {
+    fn from_iter<I: IntoIterator<Item = Range<usize>>>(iter: I) -> Self {
+        let mut set = IntervalSet::default();
+        iter.into_iter().for_each(|r| {
+            set.insert(r);
+        });
+        set
+    }
+}
+
+impl From<Range<usize>> for IntervalSet {
+    fn from(r: Range<usize>) -> Self {
+        let mut set = IntervalSet::default();
+        set.insert(r);
+        set
+    }
+}
+
+impl IntervalSet {
+    /// Iterate over the intervals, in ascending order
+    pub fn iter(&self) -> impl Iterator<Item = Range<usize>> + '_ {
+        self.intervals.iter().map(|(start, end)| *start..*end)
+    }
+
+    /// Last interval of the set, if any
+    pub fn last(&self) -> Option<Range<usize>> {
+        self.intervals
+            .iter()
+            .next_back()
+            .map(|(start, end)| *start..*end)
+    }
+
+    /// End of the interval that starts in the provided index, if any
+    pub fn end_of(&self, start: usize) -> Option<usize> {
+        self.intervals.get(&start).copied()
+    }
+
+    /// Check if the provided range is entirely contained in a single interval of the set
+    pub fn contains(&self, range: &Range<usize>) -> bool {
+        if range.start >= range.end {
+            return true;
+        }
+
+        // Since the intervals are never adjacent, a range is contained only if the interval that
+        // starts before it also ends after it
+        self.intervals
+            .range(..=range.start)
+            .next_back()
+            .map(|(_, end)| *end >= range.end)
+            .unwrap_or(false)
+    }
+
+    /// Lowest index of the set that is not contained in the provided set
+    pub fn first_excluding(&self, excluded: &IntervalSet) -> Option<usize> {
+        self.intervals.iter().find_map(|(start, end)| {
+            // The excluded intervals are never adjacent, so the end of the interval that contains
+            // the start is not excluded
+            let idx = excluded
+                .intervals
+                .range(..=*start)
+                .next_back()
+                .filter(|(_, e)| *e > start)
+                .map(|(_, e)| *e)
+                .unwrap_or(*start);
+
+            Some(idx).filter(|idx| idx < end)
+        })
+    }
+
+    /// Add the provided range to the set, merging it with any overlapping or adjacent interval.
+    ///
+    /// Returns the modified starts, so the update can be persisted and reverted.
+    pub fn insert(&mut self, range: Range<usize>) -> IntervalChanges {
+        let mut changes = vec![];
+        if range.start >= range.end {
+            return changes;
+        }
+
+        // The intervals are disjoint, so their ends are ordered as their starts
+        let merged: Vec<(usize, usize)> = self
+            .intervals
+            .range(..=range.end)
+            .rev()
+            .take_while(|(_, end)| **end >= range.start)
+            .map(|(start, end)| (*start, *end))
+            .collect();
+
+        let mut start = range.start;
+        let mut end = range.end;
+        for (s, e) in merged {
+            self.set(s, None, &mut changes);
+            start = start.min(s);
+            end = end.max(e);
+        }
+
+        self.set(start, Some(end), &mut changes);
+
+        changes
+    }
+
+    /// Remove the provided range from the set, splitting any interval that partially overlaps it.
+    ///
+    /// Returns the modified starts, so the update can be persisted and reverted.
+    pub fn remove(&mut self, range: Range<usize>) -> IntervalChanges {
+        let mut changes = vec![];
+        if range.start >= range.end {
+            return changes;
+        }
+
+        let overlapping: Vec<(usize, usize)> = self
+            .intervals
+            .range(..range.end)
+            .rev()
+            .take_while(|(_, end)| **end > range.start)
+            .map(|(start, end)| (*start, *end))
+            .collect();
+
+        for (s, e) in overlapping {
+            self.set(s, None, &mut changes);
+
+            if s < range.start {
+                self.set(s, Some(range.start), &mut changes);
+            }
+
+            if e > range.end {
+                self.set(range.end, Some(e), &mut changes);
+            }
+        }
+
+        changes
+    }
+
+    /// Revert the provided changes, returned by the latest updates of the set
+    pub fn undo(&mut self, changes: &[(usize, Option<usize>)]) {
+        for (start, end) in changes.iter().rev() {
+            match end {
+                Some(e) => self.intervals.insert(*start, *e),
+                None => self.intervals.remove(start),
+            };
+        }
+    }
+
+    /// Replace the end of the interval of the provided start, recording its previous end
+    fn set(&mut self, start: usize, end: Option<usize>, changes: &mut IntervalChanges) {
+        let previous = match end {
+            Some(e) => self.intervals.insert(start, e),
+            None => self.intervals.remove(&start),
+        };
+
+        changes.push((start, previous));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::big_merkle_small;
+    use super::IntervalSet;
+    use crate::*;
+
+    use std::ops::Range;
+    use std::sync::Arc;
+
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    const BASE: usize = 256;
+
+    /// Naive model of the interval set, with one flag per index of the base
+    fn model_intervals(bitmap: &[bool]) -> Vec<Range<usize>> {
+        let mut intervals: Vec<Range<usize>> = vec![];
+
+        for (idx, _) in bitmap.iter().enumerate().filter(|(_, b)| **b) {
+            match intervals.last_mut() {
+                Some(r) if r.end == idx => r.end = idx + 1,
+                _ => intervals.push(idx..idx + 1),
+            }
+        }
+
+        intervals
+    }
+
+    fn random_range<R: Rng>(rng: &mut R) -> Range<usize> {
+        let start = rng.gen_range(0, BASE);
+        let len = if rng.gen_bool(0.5) {
+            1
+        } else {
+            rng.gen_range(0, BASE - start + 1)
+        };
+
+        start..start + len
+    }
+
+    #[test]
+    fn interval_set_matches_bitmap() {
+        for seed in 0..32 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let mut set = IntervalSet::from(0..BASE);
+            let mut bitmap = vec![true; BASE];
+
+            for _ in 0..256 {
+                let range = random_range(&mut rng);
+                let flag = rng.gen_bool(0.5);
+
+                let previous = set.clone();
+                let changes = if flag {
+                    set.insert(range.clone())
+                } else {
+                    set.remove(range.clone())
+                };
+
+                let mut reverted = set.clone();
+                reverted.undo(&changes);
+                assert_eq!(previous, reverted);
+
+                bitmap[range].iter_mut().for_each(|b| *b = flag);
+
+                let intervals: Vec<Range<usize>> = set.iter().collect();
+                assert_eq!(model_intervals(&bitmap), intervals);
+
+                let query = random_range(&mut rng);
+                assert_eq!(
+                    bitmap[query.clone()].iter().all(|b| *b),
+                    set.contains(&query)
+                );
+
+                let idx = rng.gen_range(0, BASE);
+                assert_eq!(bitmap[idx], set.contains(&(idx..idx + 1)));
+
+                let excluded = IntervalSet::from(random_range(&mut rng));
+                let first = (0..BASE).find(|i| bitmap[*i] && !excluded.contains(&(*i..*i + 1)));
+                assert_eq!(first, set.first_excluding(&excluded));
+            }
+        }
+    }
+
+    #[test]
+    fn big_merkle_push() {
+        for mut t in big_merkle_small("big_merkle_push") {
+            for i in 0..3 {
+                assert_eq!(i, t.push(Scalar::from(i as u64)).unwrap());
+            }
+
+            t.remove(1).unwrap();
+            assert_eq!(3, t.push(Scalar::one()).unwrap());
+            assert_eq!(1, t.push_reuse(Scalar::one()).unwrap());
+            assert_eq!(4, t.push_reuse(Scalar::one()).unwrap());
+
+            // Removing the last leaf shrinks the tree
+            t.remove(4).unwrap();
+            t.remove(3).unwrap();
+            assert_eq!(3, t.size());
+            assert_eq!(3, t.push(Scalar::one()).unwrap());
+            assert_eq!(4, t.push(Scalar::one()).unwrap());
+
+            t.insert(MERKLE_WIDTH - 1, Scalar::one()).unwrap();
+            match t.push(Scalar::one()) {
+                Err(Error::FullTree) => (),
+                _ => panic!("A leaf was appended after the last index"),
+            }
+            assert_eq!(5, t.push_reuse(Scalar::one()).unwrap());
+
+            t.insert_batch((6..MERKLE_WIDTH - 1).map(|i| (i, Scalar::one())))
+                .unwrap();
+            match t.push_reuse(Scalar::one()) {
+                Err(Error::FullTree) => (),
+                _ => panic!("A leaf was inserted in a full tree"),
+            }
+        }
+
+        // The indexes of the pruned sub-trees are not reused
+        for mut t in big_merkle_small("big_merkle_push_pruned") {
+            for i in 0..8 {
+                t.push(Scalar::from(i as u64)).unwrap();
+            }
+            t.remove(2).unwrap();
+            t.prune().unwrap();
+
+            assert_eq!(8, t.push_reuse(Scalar::one()).unwrap());
+        }
+
+        for mut t in big_merkle_small("big_merkle_push_removed") {
+            let a = t.push(Scalar::from(1u64)).unwrap();
+            let b = t.push(Scalar::from(2u64)).unwrap();
+            t.remove(b).unwrap();
+            assert_eq!(b, t.push(Scalar::from(3u64)).unwrap());
+
+            t.remove(b).unwrap();
+            t.remove(a).unwrap();
+            assert_eq!(0, t.size());
+            let reopened = BigMerkleTree::<Scalar>::open_store(Arc::clone(&t.db)).unwrap();
+            assert_eq!(0, reopened.size());
+            assert_eq!(0, t.push(Scalar::one()).unwrap());
+        }
+    }
+}
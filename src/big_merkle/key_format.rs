@@ -0,0 +1,248 @@
+use super::{IntervalSet, MerkleCoord};
+use crate::store::fetch_raw;
+use crate::{Error, IterDirection, MerkleStore, StoreBatch, MERKLE_ARITY};
+
+use std::convert::TryInto;
+
+/// Key of the persisted version of the encoding of the coordinates. It cannot collide with the
+/// encoded coordinates.
+const KEY_FORMAT_KEY: &[u8] = b"key-format";
+
+/// Version of the encoding of the coordinates, with the big-endian height followed by the
+/// big-endian index. An absent version represents the legacy encoding, with the little-endian
+/// height and index serialized by bincode.
+pub(crate) const KEY_FORMAT: u8 = 1;
+
+/// Maximum number of coordinates rewritten by every batch of the migration
+const MIGRATION_BATCH: usize = 4096;
+
+/// Fetch the version of the encoding persisted in the store
+pub(crate) fn fetch(db: &dyn MerkleStore) -> Result<u8, Error> {
+    fetch_raw(db, KEY_FORMAT_KEY).map(|v| v.unwrap_or(0))
+}
+
+/// Append the persistence of the current version of the encoding to the provided batch
+pub(crate) fn batch(batch: &mut StoreBatch) -> Result<(), Error> {
+    let version = bincode::serialize(&KEY_FORMAT).map_err(|e| Error::Other(e.to_string()))?;
+    batch.put(KEY_FORMAT_KEY, version);
+
+    Ok(())
+}
+
+/// Check if the coordinates of the store are encoded with the current version
+pub(crate) fn check(db: &dyn MerkleStore) -> Result<(), Error> {
+    match fetch(db)? {
+        KEY_FORMAT => Ok(()),
+        v => Err(Error::UnsupportedVersion(v)),
+    }
+}
+
+/// Decode a coordinate with the legacy encoding.
+///
+/// The height of the current encoding always starts with zeroes, so only the legacy keys of the
+/// coordinates below the root have a non-zero first byte. The legacy key of the root is the same
+/// in both encodings.
+fn legacy_coord(key: &[u8]) -> Option<MerkleCoord> {
+    if key.len() != MerkleCoord::KEY_LEN || key[0] == 0 || key[1..8].iter().any(|b| *b != 0) {
+        return None;
+    }
+
+    let height = key[..8].try_into().ok().map(u64::from_le_bytes)?;
+    let idx = key[8..].try_into().ok().map(u64::from_le_bytes)?;
+
+    Some(MerkleCoord::new(height as usize, idx as usize))
+}
+
+/// Height and empty intervals of the leaves persisted with the legacy encoding, or `None` if the
+/// store has no legacy coordinate.
+///
+/// The legacy trees persisted the cached nodes in a separate store, so every legacy coordinate is
+/// a leaf of the tree. Will fail if the coordinates do not share the same height.
+pub(crate) fn legacy_leaves(db: &dyn MerkleStore) -> Result<Option<(usize, IntervalSet)>, Error> {
+    let mut height = None;
+    let mut leaves = IntervalSet::default();
+
+    for item in db.iter_from(&[1u8], IterDirection::Forward) {
+        let c = match legacy_coord(item?.0.as_slice()) {
+            Some(c) => c,
+            None => continue,
+        };
+
+        match height {
+            Some(h) if h != c.height => {
+                return Err(Error::Other(
+                    "The legacy coordinates do not share the same height.".to_owned(),
+                ))
+            }
+            _ => height = Some(c.height),
+        }
+
+        leaves.insert(c.idx..c.idx + 1);
+    }
+
+    Ok(height.map(|h| {
+        let mut empty = IntervalSet::from(0..MERKLE_ARITY.pow(h as u32));
+        leaves.iter().for_each(|r| {
+            empty.remove(r);
+        });
+
+        (h, empty)
+    }))
+}
+
+/// Rewrite every coordinate of the legacy encoding with the current one, returning whether the
+/// store required the migration.
+///
+/// The coordinates are rewritten in bounded batches, and the version is persisted only after the
+/// last one. An interrupted migration can be resumed, since the rewritten keys are never decoded
+/// as legacy keys.
+pub(crate) fn migrate(db: &dyn MerkleStore) -> Result<bool, Error> {
+    if fetch(db)? == KEY_FORMAT {
+        return Ok(false);
+    }
+
+    // The legacy keys below the root start with their non-zero height
+    let mut from = vec![1u8];
+    loop {
+        let legacy = db
+            .iter_from(from.as_slice(), IterDirection::Forward)
+            .filter_map(|item| match item {
+                Ok((k, v)) => legacy_coord(k.as_slice()).map(|c| Ok((k, v, c))),
+                Err(e) => Some(Err(e)),
+            })
+            .take(MIGRATION_BATCH)
+            .collect::<Result<Vec<(Vec<u8>, Vec<u8>, MerkleCoord)>, Error>>()?;
+
+        let mut batch = StoreBatch::default();
+        for (k, v, c) in legacy.iter() {
+            batch.delete(k);
+            batch.put(c.to_key(), v);
+        }
+
+        match legacy.last() {
+            Some((k, _, _)) => from = k.clone(),
+            None => {
+                self::batch(&mut batch)?;
+                db.write(batch)?;
+
+                return Ok(true);
+            }
+        }
+
+        db.write(batch)?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::KEY_FORMAT_KEY;
+    use crate::*;
+
+    use std::convert::TryFrom;
+    use std::sync::Arc;
+
+    use rocksdb::DB;
+    use tempdir::TempDir;
+
+    #[test]
+    fn key_format_migration() {
+        let store = Arc::new(MemoryStore::default());
+        let mut t = BigMerkleTree::with_store(store.clone(), MERKLE_WIDTH).unwrap();
+        for i in (0..MERKLE_WIDTH).step_by(3) {
+            t.insert(i, Scalar::from(i as u64)).unwrap();
+        }
+        t.remove(9).unwrap();
+        let root = t.root().unwrap();
+        drop(t);
+
+        // Rewrite the coordinates with the legacy encoding
+        let coords: Vec<(Vec<u8>, Vec<u8>)> = store
+            .iter_prefix(&[])
+            .map(Result::unwrap)
+            .filter(|(k, _)| k.len() == MerkleCoord::KEY_LEN)
+            .collect();
+        assert!(coords.len() > 1);
+        for (k, v) in coords {
+            let legacy = bincode::serialize(&MerkleCoord::try_from(k.as_slice()).unwrap()).unwrap();
+            store.delete(k.as_slice()).unwrap();
+            store.put(legacy.as_slice(), v.as_slice()).unwrap();
+        }
+        store.delete(KEY_FORMAT_KEY).unwrap();
+
+        match BigMerkleTree::<Scalar>::open_store(store.clone()) {
+            Err(Error::UnsupportedVersion(0)) => (),
+            _ => panic!("The legacy encoding was opened without a migration"),
+        }
+
+        assert!(BigMerkleTree::<Scalar>::migrate_store(store.as_ref()).unwrap());
+        assert!(!BigMerkleTree::<Scalar>::migrate_store(store.as_ref()).unwrap());
+
+        let t = BigMerkleTree::<Scalar>::open_store(store).unwrap();
+        assert_eq!(root, t.root().unwrap());
+        assert!(t.node_is_empty(t.height(), 9));
+
+        let proof = t.proof(12).unwrap();
+        assert!(proof.verify_at(12, &Scalar::from(12u64), &root));
+    }
+
+    #[test]
+    fn key_format_migration_legacy_layout() {
+        let db_path = TempDir::new("key_format_legacy")
+            .map(|t| t.into_path())
+            .unwrap();
+        let height =
+            BigMerkleTree::<Scalar>::with_store(Arc::new(MemoryStore::default()), MERKLE_WIDTH)
+                .unwrap()
+                .height();
+        let mut reference = MerkleTree::<Scalar>::default();
+
+        // The legacy trees persisted only the leaves, with the bincode coordinates
+        let db = DB::open_default(&db_path).unwrap();
+        for i in (0..40).step_by(3).filter(|i| *i != 9) {
+            let key = bincode::serialize(&MerkleCoord::new(height, i)).unwrap();
+            let leaf = bincode::serialize(&Scalar::from(i as u64)).unwrap();
+            db.put(key, leaf).unwrap();
+            reference.insert_unchecked(i, Scalar::from(i as u64));
+        }
+        drop(db);
+
+        assert!(BigMerkleTree::<Scalar>::open(&db_path).is_err());
+        assert!(BigMerkleTree::<Scalar>::migrate(&db_path).unwrap());
+        assert!(!BigMerkleTree::<Scalar>::migrate(&db_path).unwrap());
+
+        let mut t = BigMerkleTree::<Scalar>::open(&db_path).unwrap();
+        assert_eq!(MERKLE_WIDTH, t.width());
+        assert_eq!(40, t.size());
+        assert_eq!(reference.root(), t.root().unwrap());
+        assert!(t.node_is_empty(height, 9));
+        assert!(t.node_is_empty(height, 38));
+        assert!(!t.node_is_empty(height, 12));
+
+        let proof = t.proof(12).unwrap();
+        assert!(proof.verify_at(12, &Scalar::from(12u64), &reference.root()));
+
+        t.insert(9, Scalar::one()).unwrap();
+        reference.insert_unchecked(9, Scalar::one());
+        assert_eq!(reference.root(), t.root().unwrap());
+        drop(t);
+
+        let t = BigMerkleTree::<Scalar>::with_store(
+            Arc::new(DB::open_default(&db_path).unwrap()),
+            MERKLE_WIDTH,
+        )
+        .unwrap();
+        assert_eq!(reference.root(), t.root().unwrap());
+    }
+
+    #[test]
+    fn key_format_migration_legacy_heights() {
+        let store = MemoryStore::default();
+        for (height, idx) in [(2, 0), (3, 1)].iter() {
+            let key = bincode::serialize(&MerkleCoord::new(*height, *idx)).unwrap();
+            store.put(key.as_slice(), &[0]).unwrap();
+        }
+
+        assert!(BigMerkleTree::<Scalar>::migrate_store(&store).is_err());
+        assert!(!BigMerkleTree::<Scalar>::migrate_store(&MemoryStore::default()).unwrap());
+    }
+}
@@ -0,0 +1,139 @@
+use crate::store::fetch_raw;
+use crate::{Error, MerkleStore, StoreBatch};
+
+use std::convert::TryInto;
+use std::mem;
+
+/// Key of the persisted flag of the leaf index. It cannot collide with the serialized
+/// coordinates.
+const LEAF_INDEX_KEY: &[u8] = b"leaf-index";
+
+/// Prefix of the entries of the leaf index, followed by the serialized leaf and the big-endian
+/// index, so the indexes of a leaf are ordered.
+const ENTRY_PREFIX: &[u8] = b"lv";
+
+/// Secondary index of the leaves of a tree, mapping every serialized leaf to its indexes
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub(crate) struct LeafIndex {
+    /// Whether the index is maintained by the mutations of the tree
+    pub enabled: bool,
+}
+
+impl LeafIndex {
+    /// Fetch the leaf index state persisted in the store, if any
+    pub fn fetch(db: &dyn MerkleStore) -> Result<Self, Error> {
+        fetch_raw(db, LEAF_INDEX_KEY).map(|e| LeafIndex {
+            enabled: e.unwrap_or(false),
+        })
+    }
+
+    /// Append the persistence of the leaf index state to the provided batch
+    pub fn batch(&self, batch: &mut StoreBatch) -> Result<(), Error> {
+        let enabled = bincode::serialize(&self.enabled).map_err(|e| Error::Other(e.to_string()))?;
+        batch.put(LEAF_INDEX_KEY, enabled);
+
+        Ok(())
+    }
+
+    /// Key of the entry of the provided serialized leaf in the provided index
+    pub fn entry_key(leaf: &[u8], idx: usize) -> Vec<u8> {
+        let mut key = ENTRY_PREFIX.to_vec();
+        key.extend_from_slice(leaf);
+        key.extend_from_slice(&(idx as u64).to_be_bytes());
+        key
+    }
+
+    /// Append to the provided batch the replacement of the entry of the previous leaf of the
+    /// provided index
+    pub fn update(
+        previous: Option<&[u8]>,
+        leaf: Option<&[u8]>,
+        idx: usize,
+        batch: &mut StoreBatch,
+    ) {
+        if let Some(p) = previous {
+            batch.delete(LeafIndex::entry_key(p, idx));
+        }
+
+        if let Some(l) = leaf {
+            batch.put(LeafIndex::entry_key(l, idx), b"");
+        }
+    }
+
+    /// Indexes of the provided serialized leaf, in ascending order
+    pub fn find(db: &dyn MerkleStore, leaf: &[u8]) -> Result<Vec<usize>, Error> {
+        let prefix = LeafIndex::entry_key(leaf, 0);
+        let prefix = &prefix[..prefix.len() - mem::size_of::<u64>()];
+
+        // The leaves that start with the provided one have longer keys
+        let mut indexes = vec![];
+        for item in db.iter_prefix(prefix) {
+            let (k, _) = item?;
+            if let Ok(idx) = k[prefix.len()..].try_into().map(u64::from_be_bytes) {
+                indexes.push(idx as usize);
+            }
+        }
+
+        Ok(indexes)
+    }
+
+    /// Append to the provided batch the deletion of every entry of the index
+    pub fn clear(db: &dyn MerkleStore, batch: &mut StoreBatch) -> Result<(), Error> {
+        for item in db.iter_prefix(ENTRY_PREFIX) {
+            batch.delete(item?.0);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::big_merkle_small;
+    use crate::*;
+
+    #[test]
+    fn big_merkle_leaf_index() {
+        for mut t in big_merkle_small("big_merkle_leaf_index") {
+            let leaf = Scalar::from(7u64);
+            t.insert(5, leaf).unwrap();
+
+            match t.find(&leaf) {
+                Err(Error::LeafIndexDisabled) => (),
+                _ => panic!("The leaves were found without the index"),
+            }
+
+            // The present leaves are indexed once the index is enabled
+            t.set_leaf_index(true).unwrap();
+            t.insert_batch(vec![(2, leaf), (9, Scalar::one())]).unwrap();
+            assert_eq!(vec![2, 5], t.find(&leaf).unwrap());
+
+            let root = t.root().unwrap();
+            let proof = t.proof_for_leaf(&leaf).unwrap();
+            assert!(proof.verify_at(2, &leaf, &root));
+
+            t.remove(2).unwrap();
+            t.insert(5, Scalar::one()).unwrap();
+            assert!(!t.contains(&leaf).unwrap());
+            assert_eq!(vec![5, 9], t.find(&Scalar::one()).unwrap());
+            match t.proof_for_leaf(&leaf) {
+                Err(Error::LeafNotFound) => (),
+                _ => panic!("A proof was generated for an absent leaf"),
+            }
+
+            let c = t.checkpoint().unwrap();
+            t.push(leaf).unwrap();
+            assert_eq!(vec![10], t.find(&leaf).unwrap());
+            t.rollback_to(c).unwrap();
+            assert!(!t.contains(&leaf).unwrap());
+
+            // The pruned leaves are no longer indexed
+            t.mark(9).unwrap();
+            t.prune().unwrap();
+            assert_eq!(vec![9], t.find(&Scalar::one()).unwrap());
+
+            t.set_leaf_index(false).unwrap();
+            assert!(t.db().iter_prefix(b"lv").next().is_none());
+        }
+    }
+}
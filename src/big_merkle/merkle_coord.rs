@@ -1,15 +1,18 @@
-use crate::{Error, MERKLE_ARITY};
+use crate::store::{fetch_raw, persist_raw};
+use crate::{Error, MerkleStore, MERKLE_ARITY};
 
 use std::cmp;
 use std::convert::{TryFrom, TryInto};
 
-use rocksdb::DB;
 use serde::{Deserialize, Serialize};
 
 /// Representation of a coordinate inside the tree.
 ///
 /// No tree consistency is performed in this layer. This implies invalid coordinates are possible
 /// inside a tree.
+///
+/// The coordinates are persisted with the big-endian height followed by the big-endian index, so
+/// the order of the keys is the order of the nodes of the tree, level by level.
 #[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
 pub struct MerkleCoord {
     /// Height position in the tree
@@ -19,34 +22,41 @@ pub struct MerkleCoord {
 }
 
 impl MerkleCoord {
+    /// Length of the encoded key of a coordinate
+    pub const KEY_LEN: usize = 16;
+
     /// MerkleCoord constructor
     pub fn new(height: usize, idx: usize) -> Self {
         MerkleCoord { height, idx }
     }
 
-    /// Attempt to fetch a leaf from a DB
-    pub fn fetch_leaf<T>(self, db: &DB) -> Result<Option<T>, Error>
+    /// Encode the coordinate as a store key
+    pub fn to_key(self) -> [u8; 16] {
+        let mut key = [0u8; 16];
+        key[..8].copy_from_slice(&MerkleCoord::level_prefix(self.height));
+        key[8..].copy_from_slice(&(self.idx as u64).to_be_bytes());
+        key
+    }
+
+    /// Common prefix of the keys of every coordinate of the provided height
+    pub fn level_prefix(height: usize) -> [u8; 8] {
+        (height as u64).to_be_bytes()
+    }
+
+    /// Attempt to fetch a leaf from a store
+    pub fn fetch_leaf<T>(self, db: &dyn MerkleStore) -> Result<Option<T>, Error>
     where
         T: for<'a> Deserialize<'a>,
     {
-        let coord: Vec<u8> = self.try_into()?;
-
-        db.get(coord.as_slice())
-            .map_err(|e| Error::Other(e.to_string()))?
-            .map(|b| bincode::deserialize::<T>(b.as_ref()).map_err(|e| Error::Other(e.to_string())))
-            .transpose()
+        fetch_raw(db, &self.to_key())
     }
 
-    /// Attempt to persist a leaf into a DB
-    pub fn persist_leaf<T>(self, db: &DB, leaf: T) -> Result<(), Error>
+    /// Attempt to persist a leaf into a store
+    pub fn persist_leaf<T>(self, db: &dyn MerkleStore, leaf: T) -> Result<(), Error>
     where
         T: Serialize,
     {
-        let coord: Vec<u8> = self.try_into()?;
-        let leaf = bincode::serialize(&leaf).map_err(|e| Error::Other(e.to_string()))?;
-
-        db.put(coord.as_slice(), leaf.as_slice())
-            .map_err(|e| Error::Other(e.to_string()))
+        persist_raw(db, &self.to_key(), leaf)
     }
 
     /// Descend the tree for a number of provided levels
@@ -64,7 +74,17 @@ impl TryFrom<&[u8]> for MerkleCoord {
     type Error = Error;
 
     fn try_from(buf: &[u8]) -> Result<MerkleCoord, Self::Error> {
-        bincode::deserialize(&buf).map_err(|e| Error::Other(e.to_string()))
+        if buf.len() != MerkleCoord::KEY_LEN {
+            return Err(Error::InvalidLength);
+        }
+
+        let height = buf[..8].try_into().map(u64::from_be_bytes);
+        let idx = buf[8..].try_into().map(u64::from_be_bytes);
+
+        match (height, idx) {
+            (Ok(height), Ok(idx)) => Ok(MerkleCoord::new(height as usize, idx as usize)),
+            _ => Err(Error::InvalidLength),
+        }
     }
 }
 
@@ -72,6 +92,36 @@ impl TryInto<Vec<u8>> for MerkleCoord {
     type Error = Error;
 
     fn try_into(self) -> Result<Vec<u8>, Self::Error> {
-        bincode::serialize(&self).map_err(|e| Error::Other(e.to_string()))
+        Ok(self.to_key().to_vec())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    use std::convert::{TryFrom, TryInto};
+
+    #[test]
+    fn merkle_coord_key_order() {
+        let mut coords = vec![];
+        for height in (0..4).rev() {
+            for idx in vec![u32::max_value() as usize, 300, 256, 255, 1, 0] {
+                coords.push(MerkleCoord::new(height, idx));
+            }
+        }
+
+        let mut keys: Vec<Vec<u8>> = coords.iter().map(|c| (*c).try_into().unwrap()).collect();
+        keys.sort();
+
+        coords.sort_by_key(|c| (c.height, c.idx));
+        let decoded: Vec<MerkleCoord> = keys
+            .iter()
+            .map(|k| MerkleCoord::try_from(k.as_slice()).unwrap())
+            .collect();
+        assert_eq!(coords, decoded);
+
+        assert!(MerkleCoord::try_from(&[0u8; 15][..]).is_err());
+        assert!(keys[6].starts_with(&MerkleCoord::level_prefix(1)));
     }
 }
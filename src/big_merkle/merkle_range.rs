@@ -3,8 +3,10 @@ use crate::MERKLE_ARITY;
 use std::cmp::Ordering;
 use std::ops::Range;
 
+use serde::{Deserialize, Serialize};
+
 /// Struct to represent a range in the base of the tree
-#[derive(Debug, Eq, Clone)]
+#[derive(Serialize, Deserialize, Debug, Eq, Clone)]
 pub struct MerkleRange(pub Range<usize>);
 
 impl Ord for MerkleRange {
@@ -0,0 +1,130 @@
+use super::{IntervalSet, MerkleRange};
+use crate::store::fetch_raw;
+use crate::{Error, MerkleStore, StoreBatch, MERKLE_ARITY};
+
+use std::convert::TryInto;
+
+use serde::{Deserialize, Serialize};
+
+/// Key of the persisted metadata. It cannot collide with the serialized coordinates.
+const METADATA_KEY: &[u8] = b"metadata";
+
+/// Prefix of the persisted empty intervals, followed by the big-endian start. The value is the
+/// big-endian end.
+const EMPTY_PREFIX: &[u8] = b"ei";
+
+/// Parameters of a [`BigMerkleTree`], and its empty intervals.
+///
+/// The empty intervals are persisted apart, one key per interval, so a mutation only writes the
+/// intervals it modified. The size of the tree is derived from them.
+///
+/// [`BigMerkleTree`]: crate::BigMerkleTree
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct Metadata {
+    pub width: usize,
+    pub height: usize,
+    pub arity: usize,
+}
+
+impl Metadata {
+    /// Metadata of an empty tree with the provided width
+    pub fn new(width: usize, height: usize) -> Self {
+        Metadata {
+            width,
+            height,
+            arity: MERKLE_ARITY,
+        }
+    }
+
+    /// Empty intervals of an empty tree with the provided height.
+    ///
+    /// The initial empty interval is the whole input set. Therefore, the relative range for the
+    /// root node.
+    pub fn empty(height: usize) -> IntervalSet {
+        MerkleRange::new(height, 0, 0).0.into()
+    }
+
+    /// Fetch the metadata persisted in the store, if any
+    pub fn fetch(db: &dyn MerkleStore) -> Result<Option<Self>, Error> {
+        fetch_raw(db, METADATA_KEY)
+    }
+
+    /// Append the persistence of the metadata to the provided batch
+    pub fn batch(&self, batch: &mut StoreBatch) -> Result<(), Error> {
+        let metadata = bincode::serialize(self).map_err(|e| Error::Other(e.to_string()))?;
+        batch.put(METADATA_KEY, metadata);
+
+        Ok(())
+    }
+
+    /// Key of the persisted empty interval with the provided start
+    fn empty_key(start: usize) -> Vec<u8> {
+        let mut key = EMPTY_PREFIX.to_vec();
+        key.extend_from_slice(&(start as u64).to_be_bytes());
+        key
+    }
+
+    /// Fetch the empty intervals persisted in the store
+    pub fn fetch_empty(db: &dyn MerkleStore) -> Result<IntervalSet, Error> {
+        db.iter_prefix(EMPTY_PREFIX)
+            .map(|item| {
+                let (k, v) = item?;
+                let start = k[EMPTY_PREFIX.len()..].try_into().map(u64::from_be_bytes);
+                let end = v.as_slice().try_into().map(u64::from_be_bytes);
+
+                match (start, end) {
+                    (Ok(s), Ok(e)) => Ok(s as usize..e as usize),
+                    _ => Err(Error::Other("Malformed empty interval.".to_owned())),
+                }
+            })
+            .collect()
+    }
+
+    /// Append to the provided batch the persistence of the current interval of every provided
+    /// start, or its deletion if absent
+    pub fn batch_empty<I: IntoIterator<Item = usize>>(
+        set: &IntervalSet,
+        starts: I,
+        batch: &mut StoreBatch,
+    ) {
+        for start in starts {
+            match set.end_of(start) {
+                Some(end) => batch.put(Metadata::empty_key(start), (end as u64).to_be_bytes()),
+                None => batch.delete(Metadata::empty_key(start)),
+            }
+        }
+    }
+
+    /// Append to the provided batch the replacement of the persisted empty intervals, from the
+    /// provided set to the other one
+    pub fn batch_empty_diff(from: &IntervalSet, to: &IntervalSet, batch: &mut StoreBatch) {
+        let starts = from
+            .iter()
+            .filter(|r| to.end_of(r.start) != Some(r.end))
+            .chain(to.iter().filter(|r| from.end_of(r.start) != Some(r.end)))
+            .map(|r| r.start);
+
+        Metadata::batch_empty(to, starts, batch);
+    }
+
+    /// Check if the persisted metadata was created with the provided parameters
+    pub fn check(&self, width: usize, height: usize) -> Result<(), Error> {
+        let parameters = [
+            ("arity", MERKLE_ARITY, self.arity),
+            ("width", width, self.width),
+            ("height", height, self.height),
+        ];
+
+        match parameters
+            .iter()
+            .find(|(_, expected, found)| expected != found)
+        {
+            Some((parameter, expected, found)) => Err(Error::MetadataMismatch {
+                parameter,
+                expected: *expected,
+                found: *found,
+            }),
+            None => Ok(()),
+        }
+    }
+}
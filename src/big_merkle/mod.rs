@@ -1,90 +1,353 @@
-use crate::{Error, Poseidon, PoseidonLeaf, Scalar, MERKLE_ARITY};
+use crate::store::fetch_raw;
+use crate::{
+    Error, IterDirection, MerkleStore, Poseidon, PoseidonLeaf, RootHistory, Scalar, StoreBatch,
+    MERKLE_ARITY,
+};
 
 use std::cmp;
-use std::convert::TryInto;
-use std::ops;
+use std::collections::btree_map::Entry;
+use std::collections::{BTreeMap, HashSet};
+use std::convert::{TryFrom, TryInto};
+use std::iter;
+use std::ops::{self, Range};
 use std::path::Path;
-use std::sync::{mpsc, Arc, Mutex};
-use std::thread;
+use std::sync::{Arc, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
 
+#[cfg(test)]
+use crate::{MemoryStore, StoreIter, MERKLE_WIDTH};
 use rocksdb::DB;
 #[cfg(test)]
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+#[cfg(test)]
 use tempdir::TempDir;
 
+#[cfg(feature = "async")]
+pub use async_tree::AsyncBigMerkleTree;
+pub use builder::BigMerkleTreeBuilder;
+pub use checkpoint::CheckpointId;
+pub use consistency::ConsistencyProof;
 pub use merkle_coord::MerkleCoord;
 pub use merkle_range::MerkleRange;
+pub use node_cache::{CacheConfig, CacheStats};
+pub use overlay::TreeOverlay;
 pub use proof::BigProof;
+pub use snapshot::Snapshot;
+
+use builder::height_of;
+use checkpoint::{Checkpoint, Checkpoints};
+use history::History;
+use interval_set::{IntervalChanges, IntervalSet};
+use leaf_index::LeafIndex;
+use metadata::Metadata;
+use node_cache::NodeCache;
+use pruning::Pruning;
+
+/// Key of the persisted root history. It cannot collide with the serialized coordinates.
+const ROOT_HISTORY_KEY: &[u8] = b"root-history";
+
+/// Key of the flag of a tree that shares its store with the records of another structure, which
+/// are not restored by a rollback. It cannot collide with the serialized coordinates.
+const EXTERNAL_RECORDS_KEY: &[u8] = b"external-records";
+
+/// Default number of roots retained by the root history
+const ROOT_HISTORY_CAPACITY: usize = 256;
+
+/// Nodes of the empty sub-trees, indexed by the number of levels below them.
+///
+/// Empty leaves are absent, and every empty node above is the hash of its empty children. This
+/// is the same construction of [`MerkleTree`], so both trees will produce the same roots.
+///
+/// [`MerkleTree`]: crate::MerkleTree
+pub(crate) fn empty_nodes<T: PoseidonLeaf>(height: usize) -> Vec<Option<T>>
+where
+    Scalar: ops::Mul<T, Output = T>,
+{
+    let mut empty = Vec::with_capacity(height + 1);
+    let mut h = Poseidon::default();
+
+    empty.push(None);
+    for levels in 0..height {
+        h.replace(&[empty[levels]; MERKLE_ARITY]);
+        empty.push(Some(h.hash()));
+    }
 
-const CACHE_HEIGHT_INTERVAL: usize = 2;
+    empty
+}
 
+#[cfg(feature = "async")]
+mod async_tree;
+mod builder;
+mod checkpoint;
+mod consistency;
+mod history;
+mod interval_set;
+mod key_format;
+mod leaf_index;
 mod merkle_coord;
 mod merkle_range;
+mod metadata;
+mod node_cache;
+mod overlay;
+mod pool;
 mod proof;
+mod pruning;
+mod snapshot;
 
 /// The merkle tree will accept up to `MERKLE_ARITY * MERKLE_WIDTH` leaves.
+///
+/// The leaves, the cached nodes and the metadata of the tree share a single store. Every
+/// mutation is written in one atomic batch, so an interrupted write will never leave cached
+/// nodes that disagree with the leaves.
+///
+/// The clones of the tree are handles to the same tree. The reads take `&self` and can be
+/// performed by many threads concurrently, while the mutations are serialized. Every operation
+/// observes a single version of the tree, and a [`Snapshot`] extends this to a sequence of reads.
 #[derive(Debug)]
-pub struct BigMerkleTree {
+pub struct BigMerkleTree<T: PoseidonLeaf> {
     width: usize,
     height: usize,
-    max_idx: usize,
+    /// Precalculated nodes of the empty sub-trees, indexed by the number of levels below them
+    empty: Vec<Option<T>>,
+    /// Number of workers used to calculate the root
+    threads: usize,
+    /// Mutable state, shared between the handles of the tree
+    state: Arc<RwLock<State<T>>>,
+    db: Arc<dyn MerkleStore>,
+}
+
+/// State of a tree that changes with its mutations.
+///
+/// The readers hold the read lock of the state for the whole operation, and the writers hold the
+/// write lock until the batch is written and the cached nodes are invalidated.
+#[derive(Debug)]
+struct State<T: PoseidonLeaf> {
     /// For most cases, this attribute should hold one element that represents the higher idx to
     /// the end of the tree. The usage of the free intervals is, however, non-restricted.
-    empty_intervals: Vec<MerkleRange>,
-    db: Arc<DB>,
-    cache: Arc<DB>,
+    empty_intervals: IntervalSet,
+    /// Changes of the empty intervals by the ongoing mutation, to be persisted or reverted
+    empty_changes: IntervalChanges,
+    /// Retained checkpoints, and the previous leaves of the modifications since the latest one
+    checkpoints: Checkpoints,
+    checkpoint: Option<Checkpoint>,
+    /// Current version, and the retained versions of the history
+    history: History,
+    /// Marked leaves, and the discarded ranges of the base
+    pruning: Pruning,
+    /// Whether the leaves are indexed by value in the store
+    leaf_index: LeafIndex,
+    /// Recorded roots, keyed by epoch
+    roots: RootHistory<T>,
+    /// In-memory nodes
+    cache: NodeCache<T>,
+}
+
+impl<T: PoseidonLeaf> State<T> {
+    /// Remove the provided range of the base from the empty intervals
+    fn fill_empty(&mut self, range: Range<usize>) {
+        let changes = self.empty_intervals.remove(range);
+        self.empty_changes.extend(changes);
+    }
+
+    /// Add the provided index of the base to the empty intervals
+    fn extend_empty(&mut self, idx: usize) {
+        let changes = self.empty_intervals.insert(idx..idx + 1);
+        self.empty_changes.extend(changes);
+    }
 }
 
-impl Clone for BigMerkleTree {
+impl<T: PoseidonLeaf> Clone for BigMerkleTree<T> {
     fn clone(&self) -> Self {
         BigMerkleTree {
-            max_idx: self.max_idx,
+            state: Arc::clone(&self.state),
             db: Arc::clone(&self.db),
-            cache: Arc::clone(&self.cache),
-            empty_intervals: self.empty_intervals.clone(),
+            empty: self.empty.clone(),
+            threads: self.threads,
             width: self.width,
             height: self.height,
         }
     }
 }
 
-impl BigMerkleTree {
-    /// `BigMerkleTree` constructor
+impl<T: PoseidonLeaf> BigMerkleTree<T>
+where
+    Scalar: ops::Mul<T, Output = T>,
+{
+    /// `BigMerkleTree` constructor, persisting the tree in RocksDB with the default options.
+    ///
+    /// The cached nodes are now persisted in the DB of the tree, so `cache_path` is ignored, and
+    /// any DB previously created there can be removed.
+    #[deprecated(note = "the cached nodes share the DB of the tree; use `BigMerkleTree::create`")]
     pub fn new<D: AsRef<Path>, E: AsRef<Path>>(
         db_path: D,
-        cache_path: E,
+        _cache_path: E,
         width: usize,
     ) -> Result<Self, Error> {
-        let max_idx = 0;
-        let height = width as f64;
-        let height = height.log(MERKLE_ARITY as f64) as usize;
+        Self::create(db_path, width)
+    }
+
+    /// `BigMerkleTree` constructor, persisting the tree in RocksDB with the default options.
+    ///
+    /// Use [`BigMerkleTreeBuilder`] to customize the store and the caching.
+    ///
+    /// If the DB already holds a tree, its state is restored. Will fail if the persisted tree was
+    /// created with different parameters.
+    pub fn create<D: AsRef<Path>>(db_path: D, width: usize) -> Result<Self, Error> {
+        let db = DB::open_default(db_path).map_err(|e| Error::Other(e.to_string()))?;
 
-        let mut empty_intervals = Vec::new();
+        Self::with_store(Arc::new(db), width)
+    }
 
+    /// Restore a `BigMerkleTree` previously persisted in RocksDB.
+    ///
+    /// Will fail if the DB does not hold a tree.
+    pub fn open<D: AsRef<Path>>(db_path: D) -> Result<Self, Error> {
         let db = DB::open_default(db_path).map_err(|e| Error::Other(e.to_string()))?;
-        let db = Arc::new(db);
 
-        let cache = DB::open_default(cache_path).map_err(|e| Error::Other(e.to_string()))?;
-        let cache = Arc::new(cache);
+        Self::open_store(Arc::new(db))
+    }
+
+    /// `BigMerkleTree` constructor for the provided storage backend.
+    ///
+    /// If the store already holds a tree, its state is restored. Will fail if the width is not a
+    /// power of `MERKLE_ARITY`, or if the persisted tree was created with different parameters.
+    pub fn with_store(db: Arc<dyn MerkleStore>, width: usize) -> Result<Self, Error> {
+        let height = height_of(width)?;
+
+        let metadata = match Metadata::fetch(db.as_ref())? {
+            Some(m) => {
+                key_format::check(db.as_ref())?;
+                m.check(width, height).map(|_| m)?
+            }
+            None => {
+                let m = Metadata::new(width, height);
+
+                let mut batch = StoreBatch::default();
+                m.batch(&mut batch)?;
+                Metadata::batch_empty_diff(
+                    &IntervalSet::default(),
+                    &Metadata::empty(height),
+                    &mut batch,
+                );
+                key_format::batch(&mut batch)?;
+                db.write(batch)?;
+
+                m
+            }
+        };
+
+        Self::from_metadata(db, metadata)
+    }
+
+    /// Restore a `BigMerkleTree` previously persisted in the provided storage backend.
+    ///
+    /// Will fail if the store does not hold a tree.
+    pub fn open_store(db: Arc<dyn MerkleStore>) -> Result<Self, Error> {
+        let metadata = Metadata::fetch(db.as_ref())?.ok_or_else(|| {
+            Error::Other("The provided store does not contain a merkle tree.".to_owned())
+        })?;
+
+        // Only the arity, defined in compile time, can differ
+        key_format::check(db.as_ref())?;
+        metadata.check(metadata.width, metadata.height)?;
 
-        // The initial empty interval is the whole input set. Therefore, the relative range for the
-        // root node.
-        empty_intervals.push(MerkleRange::new(height, 0, 0));
+        Self::from_metadata(db, metadata)
+    }
+
+    /// Migrate a tree persisted in RocksDB with the legacy encoding of the coordinates.
+    ///
+    /// See [`BigMerkleTree::migrate_store`].
+    pub fn migrate<D: AsRef<Path>>(db_path: D) -> Result<bool, Error> {
+        let db = DB::open_default(db_path).map_err(|e| Error::Other(e.to_string()))?;
+
+        Self::migrate_store(&db)
+    }
+
+    /// Rewrite the coordinates persisted with the legacy encoding, of bincode little-endian
+    /// integers, with the ordered encoding of [`MerkleCoord`].
+    ///
+    /// The trees persisted with the legacy encoding cannot be opened until migrated. Returns
+    /// whether the store required the migration. An interrupted migration can be resumed.
+    ///
+    /// The legacy trees persisted only their leaves, so the metadata and the empty intervals are
+    /// rebuilt from the legacy coordinates. The width is derived from the height of the leaves.
+    pub fn migrate_store(db: &dyn MerkleStore) -> Result<bool, Error> {
+        if Metadata::fetch(db)?.is_none() {
+            let (height, empty) = match key_format::legacy_leaves(db)? {
+                Some(l) => l,
+                None => return Ok(false),
+            };
+
+            // The metadata is persisted before the rewrite, so an interrupted migration is
+            // resumed as the one of a tree with metadata
+            let mut batch = StoreBatch::default();
+            Metadata::new(MERKLE_ARITY.pow(height as u32), height).batch(&mut batch)?;
+            Metadata::batch_empty_diff(&IntervalSet::default(), &empty, &mut batch);
+            db.write(batch)?;
+        }
+
+        key_format::migrate(db)
+    }
+
+    fn from_metadata(db: Arc<dyn MerkleStore>, metadata: Metadata) -> Result<Self, Error> {
+        let Metadata { width, height, .. } = metadata;
+
+        let empty = empty_nodes(height);
+
+        let checkpoints = Checkpoints::fetch(db.as_ref())?;
+        let checkpoint = match checkpoints.ids.back() {
+            Some(id) => Some(Checkpoint::fetch(db.as_ref(), *id)?),
+            None => None,
+        };
+        let history = History::fetch(db.as_ref())?;
+        let pruning = Pruning::fetch(db.as_ref())?;
+        let leaf_index = LeafIndex::fetch(db.as_ref())?;
+        let empty_intervals = Metadata::fetch_empty(db.as_ref())?;
+        let roots = fetch_raw(db.as_ref(), ROOT_HISTORY_KEY)?
+            .unwrap_or_else(|| RootHistory::new(ROOT_HISTORY_CAPACITY));
+
+        let state = State {
+            empty_intervals,
+            empty_changes: vec![],
+            checkpoints,
+            checkpoint,
+            history,
+            pruning,
+            leaf_index,
+            roots,
+            cache: NodeCache::new(CacheConfig::default()),
+        };
 
         Ok(BigMerkleTree {
-            max_idx,
+            empty,
+            threads: num_cpus::get(),
+            state: Arc::new(RwLock::new(state)),
             db,
-            cache,
-            empty_intervals,
             width,
             height,
         })
     }
 
-    /// Return a reference to the internal path of the DB
-    pub fn db_path(&self) -> &Path {
+    /// Lock the state for a read, waiting for the current mutation to finish
+    fn state(&self) -> RwLockReadGuard<'_, State<T>> {
+        // A failed mutation reverts the state before returning, so a poisoned state is usable
+        self.state.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Lock the state for a mutation, waiting for the current readers to finish
+    fn state_mut(&self) -> RwLockWriteGuard<'_, State<T>> {
+        self.state.write().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Return a reference to the internal path of the DB, if persisted in the file system
+    pub fn db_path(&self) -> Option<&Path> {
         self.db.path()
     }
 
+    /// Return a reference to the store that persists the tree
+    pub(crate) fn db(&self) -> &dyn MerkleStore {
+        self.db.as_ref()
+    }
+
     /// Height of the tree
     pub fn height(&self) -> usize {
         self.height
@@ -100,13 +363,53 @@ impl BigMerkleTree {
         self.width
     }
 
+    /// Number of workers used to calculate the root. Defaults to the number of CPUs.
+    pub fn threads(&self) -> usize {
+        self.threads
+    }
+
+    /// Set the number of workers used to calculate the root. At least one worker is used.
+    pub fn set_threads(&mut self, threads: usize) {
+        self.threads = cmp::max(threads, 1);
+    }
+
+    /// Consistent view of the current version of the tree, for a sequence of reads.
+    ///
+    /// The mutations of every handle of the tree wait until the snapshot is dropped, so a thread
+    /// must not mutate the tree while it holds a snapshot, or it will wait for itself.
+    pub fn snapshot(&self) -> Snapshot<'_, T> {
+        Snapshot::new(self)
+    }
+
+    /// Number of leaves up to the last present one.
+    ///
+    /// For an append-only tree, this is the number of inserted leaves. Removing the last present
+    /// leaf shrinks the tree up to the previous one.
+    pub fn size(&self) -> usize {
+        self.size_in(&self.state())
+    }
+
+    fn size_in(&self, s: &State<T>) -> usize {
+        // Every index after the last present leaf is in the last empty interval
+        match s.empty_intervals.last() {
+            Some(r) if r.end == self.width => r.start,
+            _ => self.width,
+        }
+    }
+
     /// Divide the tree into a parallelizable path to the root
     pub fn segments(&self) -> Vec<MerkleCoord> {
+        self.segments_in(&self.state())
+    }
+
+    fn segments_in(&self, s: &State<T>) -> Vec<MerkleCoord> {
         let mut coords = vec![];
-        let mut coord = MerkleCoord::new(self.height, self.max_idx);
+        let mut coord = MerkleCoord::new(self.height, self.size_in(s).saturating_sub(1));
+
+        let interval = cmp::max(s.cache.config().disk_interval, 1);
 
         while coord.height > 0 {
-            coord.descend(CACHE_HEIGHT_INTERVAL);
+            coord.descend(interval);
 
             for i in 0..coord.idx + 1 {
                 coords.push(MerkleCoord::new(coord.height, i));
@@ -116,228 +419,1006 @@ impl BigMerkleTree {
         coords
     }
 
+    /// Iterate over the nodes persisted in the store for the provided height and range of
+    /// indexes, in ascending order of index.
+    ///
+    /// For the height of the leaves, these are the present leaves. For the upper heights, these
+    /// are the nodes cached in the store. The iteration is not isolated from the mutations of the
+    /// tree.
+    pub fn iter_level(
+        &self,
+        height: usize,
+        range: Range<usize>,
+    ) -> impl Iterator<Item = Result<(usize, T), Error>> + '_ {
+        let from = MerkleCoord::new(height, range.start).to_key();
+        let to = MerkleCoord::new(height, range.end).to_key();
+
+        self.db
+            .iter_from(&from, IterDirection::Forward)
+            .take_while(move |item| match item {
+                Ok((k, _)) => k.as_slice() < &to[..],
+                Err(_) => true,
+            })
+            .map(|item| {
+                let (k, v) = item?;
+                let coord = MerkleCoord::try_from(k.as_slice())?;
+                let node =
+                    bincode::deserialize(v.as_slice()).map_err(|e| Error::Other(e.to_string()))?;
+
+                Ok((coord.idx, node))
+            })
+    }
+
+    /// Iterate over the present leaves of the sub-tree of the provided node, in ascending order
+    /// of index
+    pub fn iter_subtree(
+        &self,
+        height: usize,
+        idx: usize,
+    ) -> impl Iterator<Item = Result<(usize, T), Error>> + '_ {
+        let range = MerkleRange::new(self.height, height, idx).0;
+        self.iter_level(self.height, range)
+    }
+
     /// Check if the node in the provided height and index belongs to an empty super tree.
     pub fn node_is_empty(&self, height: usize, idx: usize) -> bool {
+        self.node_is_empty_in(&self.state(), height, idx)
+    }
+
+    fn node_is_empty_in(&self, s: &State<T>, height: usize, idx: usize) -> bool {
         let r = MerkleRange::new(self.height, height, idx);
-        self.empty_intervals.contains(&r)
+        s.empty_intervals.contains(&r.0)
     }
 
     /// Insert the provided leaf on the provided index
-    pub fn insert<T: PoseidonLeaf>(&mut self, idx: usize, leaf: T) -> Result<(), Error> {
-        self.insert_height(self.height, idx, leaf)
+    pub fn insert(&mut self, idx: usize, leaf: T) -> Result<(), Error> {
+        self.insert_batch(iter::once((idx, leaf)))
     }
 
-    /// Insert the provided leaf on the provided index
-    fn insert_height<T: PoseidonLeaf>(
+    /// Insert a set of leaves, atomically.
+    ///
+    /// All the leaves and the updated metadata are written in a single batch, and every cached
+    /// node above the inserted leaves is invalidated only once. If the same index is provided
+    /// more than once, the last leaf prevails.
+    pub fn insert_batch<I: IntoIterator<Item = (usize, T)>>(
         &mut self,
-        height: usize,
-        idx: usize,
-        leaf: T,
+        leaves: I,
     ) -> Result<(), Error> {
-        let coord = MerkleCoord::new(height, idx);
+        self.update_batch(leaves.into_iter().map(|(idx, leaf)| (idx, Some(leaf))))
+    }
 
-        if height == self.height {
-            coord
-                .persist_leaf(&self.db, leaf)
-                .and_then(|_| self.inserted(idx))
-        } else {
-            coord.persist_leaf(&self.cache, leaf)
-        }
+    /// Insert or remove a set of leaves, atomically.
+    ///
+    /// An absent leaf represents a removal. If the same index is provided more than once, the
+    /// last update prevails.
+    pub(crate) fn update_batch<I: IntoIterator<Item = (usize, Option<T>)>>(
+        &self,
+        leaves: I,
+    ) -> Result<(), Error> {
+        self.update_batch_with(StoreBatch::default(), leaves)
     }
 
-    /// Flag the provided index as inserted in the structure.
+    /// Insert or remove a set of leaves, in the same atomic write of the provided batch.
     ///
-    /// This will reorganize the empty intervals.
-    pub fn inserted(&mut self, idx: usize) -> Result<(), Error> {
-        self.max_idx = cmp::max(self.max_idx, idx);
-
-        // Should split the empty interval only if the current idx belongs to an empty base
-        if self.node_is_empty(self.height, idx) {
-            // The range for the current idx is always itself + 1, since its possible to insert
-            // leaves only on the base
-            let idx_r: MerkleRange = (idx..idx + 1).into();
-
-            let mut r1 = None;
-            let mut r2 = None;
-            let mut empty_idx = None;
-
-            // Find the empty interval that should be split
-            for (i, r) in self.empty_intervals.iter().enumerate() {
-                if r == &idx_r {
-                    r1 = Some(r.clone());
-                    r2 = Some(r.clone());
-                    empty_idx = Some(i);
+    /// This allows the structures built over the tree to persist their own records along with
+    /// the leaves.
+    pub(crate) fn update_batch_with<I: IntoIterator<Item = (usize, Option<T>)>>(
+        &self,
+        batch: StoreBatch,
+        leaves: I,
+    ) -> Result<(), Error> {
+        self.update_batch_in(&mut self.state_mut(), batch, leaves)
+    }
+
+    /// Flag the store of the tree as holding the records of another structure, persisted with
+    /// [`BigMerkleTree::update_batch_with`].
+    ///
+    /// The records are not restored by [`BigMerkleTree::rollback_to`], so the rollback of a
+    /// flagged tree is rejected.
+    pub(crate) fn flag_external_records(&self) -> Result<(), Error> {
+        let _s = self.state_mut();
+
+        let mut batch = StoreBatch::default();
+        batch.put(EXTERNAL_RECORDS_KEY, [1u8]);
+        self.db.write(batch)
+    }
+
+    fn update_batch_in<I: IntoIterator<Item = (usize, Option<T>)>>(
+        &self,
+        s: &mut State<T>,
+        mut batch: StoreBatch,
+        leaves: I,
+    ) -> Result<(), Error> {
+        let mut leaves: Vec<(usize, Option<T>)> = leaves.into_iter().collect();
+        if leaves.is_empty() {
+            return if batch.is_empty() {
+                Ok(())
+            } else {
+                self.db.write(batch)
+            };
+        } else if leaves.iter().any(|(idx, _)| *idx >= self.width) {
+            return Err(Error::IndexOutOfBounds);
+        }
+
+        // The sort is stable, so the order of repeated indexes is preserved
+        leaves.sort_by_key(|(idx, _)| *idx);
+
+        for (idx, leaf) in leaves.iter() {
+            let coord: Vec<u8> = MerkleCoord::new(self.height, *idx).try_into()?;
+
+            match leaf {
+                Some(l) => {
+                    let l = bincode::serialize(l).map_err(|e| Error::Other(e.to_string()))?;
+                    batch.put(coord, l);
                 }
+                None => batch.delete(coord),
             }
+        }
+
+        let indexes: Vec<usize> = leaves.iter().map(|(idx, _)| *idx).collect();
+
+        // Only the last update of every index is applied to the empty intervals
+        leaves.reverse();
+        leaves.dedup_by_key(|(idx, _)| *idx);
+        leaves.reverse();
+
+        for (idx, leaf) in leaves.iter() {
+            let leaf = leaf
+                .map(|l| bincode::serialize(&l))
+                .transpose()
+                .map_err(|e| Error::Other(e.to_string()))?;
+
+            self.index_leaf(s, *idx, leaf.as_deref(), &mut batch)?;
+        }
 
-            // If the interval is not found, then we have unreachable code since the
-            // `node_is_empty` check was performed
-            let mut r1 = r1.ok_or(Error::IndexOutOfBounds)?;
-            let mut r2 = r2.ok_or(Error::IndexOutOfBounds)?;
-            let empty_idx = empty_idx.ok_or(Error::IndexOutOfBounds)?;
-
-            // The rightmost of the interval is always split
-            r2.0.start = idx + 1;
-            self.empty_intervals[empty_idx] = r2;
-
-            // The leftmost of the interval is split only if idx is not the first element of the
-            // provided interval
-            //
-            // Since the base should be, but not necessarily is, append only, this should lead to
-            // performance degradation
-            if idx > r1.0.start {
-                r1.0.end = idx;
-                self.empty_intervals.push(r1);
+        // Every run of consecutive inserted indexes is removed from the empty intervals at once
+        let mut runs: Vec<Range<usize>> = vec![];
+        let mut removed = vec![];
+        for (idx, leaf) in leaves.iter() {
+            match (leaf, runs.last_mut()) {
+                (None, _) => removed.push(*idx),
+                (Some(_), Some(r)) if *idx == r.end => r.end = idx + 1,
+                (Some(_), _) => runs.push(*idx..idx + 1),
             }
         }
 
-        self.modified(idx)
+        self.commit(s, batch, indexes.as_slice(), |s| {
+            runs.into_iter().for_each(|r| s.fill_empty(r));
+
+            removed.into_iter().for_each(|idx| s.extend_empty(idx));
+
+            Ok(())
+        })
+    }
+
+    /// Append the provided leaf after the last present one, returning its index.
+    ///
+    /// Will fail with [`Error::FullTree`] if the last index of the base is occupied.
+    pub fn push(&mut self, leaf: T) -> Result<usize, Error> {
+        let mut s = self.state_mut();
+
+        let idx = self.size_in(&s);
+        if idx >= self.width {
+            return Err(Error::FullTree);
+        }
+
+        self.update_batch_in(&mut s, StoreBatch::default(), iter::once((idx, Some(leaf))))
+            .map(|_| idx)
+    }
+
+    /// Insert the provided leaf in the lowest empty index, returning it.
+    ///
+    /// The indexes of the pruned sub-trees are never reused. Will fail with
+    /// [`Error::FullTree`] if there is no empty index.
+    pub fn push_reuse(&mut self, leaf: T) -> Result<usize, Error> {
+        let mut s = self.state_mut();
+
+        let idx = s
+            .empty_intervals
+            .first_excluding(&s.pruning.pruned)
+            .ok_or(Error::FullTree)?;
+
+        self.update_batch_in(&mut s, StoreBatch::default(), iter::once((idx, Some(leaf))))
+            .map(|_| idx)
+    }
+
+    /// Layer speculative changes over the current version of the tree, without modifying it
+    /// until committed.
+    ///
+    /// The overlay does not lock the tree, so several overlays can be built side by side. Only
+    /// the first one to be committed is written; the others are then stale.
+    pub fn overlay(&self) -> TreeOverlay<'_, T> {
+        TreeOverlay::new(self)
+    }
+
+    /// Flag the provided index as inserted in the structure.
+    ///
+    /// This will reorganize the empty intervals.
+    pub fn inserted(&mut self, idx: usize) -> Result<(), Error> {
+        self.commit(&mut self.state_mut(), StoreBatch::default(), &[idx], |s| {
+            s.fill_empty(idx..idx + 1);
+            Ok(())
+        })
     }
 
     /// Set the provided leaf index as absent for the hash calculation.
     pub fn remove(&mut self, idx: usize) -> Result<(), Error> {
-        let coord: Vec<u8> = MerkleCoord::new(self.height, idx).try_into()?;
-
-        self.db
-            .delete(coord.as_slice())
-            .map_err(|e| Error::Other(e.to_string()))
-            .and_then(|_| self.removed(idx))
+        self.update_batch(iter::once((idx, None)))
     }
 
     /// Flag the provided index as absent.
     ///
     /// This will reorganize the empty intervals.
     pub fn removed(&mut self, idx: usize) -> Result<(), Error> {
-        // Check if there is an adjacent left interval
-        let left = idx > 0 && self.node_is_empty(self.height, idx - 1);
-
-        // Check if there is an adjacent right interval
-        let right = idx < self.width - 1 && self.node_is_empty(self.height, idx + 1);
-
-        if left && right {
-            // Merge the two intervals
-
-            // Fetch the index of the left and right intervals
-            let r1 = MerkleRange::from(idx - 1..idx);
-            let r2 = MerkleRange::from(idx + 1..idx + 2);
-            let mut idx_left = None;
-            let mut idx_right = None;
-            for (i, r) in self.empty_intervals.iter().enumerate() {
-                if r == &r1 {
-                    idx_left = Some(i);
+        self.commit(&mut self.state_mut(), StoreBatch::default(), &[idx], |s| {
+            s.extend_empty(idx);
+            Ok(())
+        })
+    }
+
+    /// Atomically write the provided batch, along with the modified empty intervals and the
+    /// deletion of every cached node above the modified base indexes.
+    ///
+    /// The provided closure updates the in-memory state, and its changes are reverted if the
+    /// batch is not written. The caller holds the write lock of the state until the batch is
+    /// written, so the readers will observe either the previous or the new version of the tree.
+    fn commit<F>(
+        &self,
+        s: &mut State<T>,
+        mut batch: StoreBatch,
+        indexes: &[usize],
+        update: F,
+    ) -> Result<(), Error>
+    where
+        F: FnOnce(&mut State<T>) -> Result<(), Error>,
+    {
+        if indexes
+            .iter()
+            .any(|idx| self.is_pruned_in(s, self.height, *idx))
+        {
+            return Err(Error::LeafPruned);
+        }
+
+        let history = s.history.clone();
+        s.empty_changes.clear();
+
+        let written = self
+            .record_leaves(s, indexes, &mut batch)
+            .and_then(|_| self.log_history(s, indexes, &mut batch))
+            .and_then(|_| update(s))
+            .and_then(|_| {
+                // Only the modified intervals are written
+                let starts = s.empty_changes.iter().map(|(start, _)| *start);
+                Metadata::batch_empty(&s.empty_intervals, starts, &mut batch);
+
+                self.modified(s, indexes, &mut batch)
+            })
+            .and_then(|_| self.db.write(batch));
+
+        if written.is_err() {
+            s.empty_intervals.undo(&s.empty_changes);
+            s.history = history;
+        }
+        s.empty_changes.clear();
+
+        written
+    }
+
+    /// Increment the version of the tree and, if the history is enabled, log the current value of
+    /// the provided base indexes as their value in the previous version
+    fn log_history(
+        &self,
+        s: &mut State<T>,
+        indexes: &[usize],
+        batch: &mut StoreBatch,
+    ) -> Result<(), Error> {
+        s.history.version += 1;
+
+        if s.history.horizon.is_some() {
+            let mut logged = HashSet::new();
+
+            for idx in indexes.iter().filter(|idx| logged.insert(**idx)) {
+                let coord: Vec<u8> = MerkleCoord::new(self.height, *idx).try_into()?;
+                let leaf = self.db.get(coord.as_slice())?;
+                let leaf = bincode::serialize(&leaf).map_err(|e| Error::Other(e.to_string()))?;
+
+                batch.put(History::log_key(s.history.version, *idx), leaf);
+            }
+
+            s.history.prune(self.db.as_ref(), batch)?;
+        }
+
+        s.history.batch(batch)
+    }
+
+    /// Write the provided batch as a new version of the tree without leaf changes, so the
+    /// overlays over the previous version are stale
+    fn write_version(&self, s: &mut State<T>, mut batch: StoreBatch) -> Result<(), Error> {
+        let history = s.history.clone();
+
+        let written = self
+            .log_history(s, &[], &mut batch)
+            .and_then(|_| self.db.write(batch));
+
+        if written.is_err() {
+            s.history = history;
+        }
+
+        written
+    }
+
+    /// Current version of the tree, incremented by every mutation
+    pub fn version(&self) -> u64 {
+        self.state().history.version
+    }
+
+    /// Oldest version that can be restored by [`BigMerkleTree::root_at`] and
+    /// [`BigMerkleTree::proof_at`], if the history is enabled
+    pub fn oldest_version(&self) -> Option<u64> {
+        self.state().history.oldest()
+    }
+
+    /// Retain the provided number of past versions, or disable the history if absent.
+    ///
+    /// The history starts from the current version, and the versions older than the horizon are
+    /// pruned as the tree is mutated.
+    pub fn set_history_horizon(&mut self, horizon: Option<u64>) -> Result<(), Error> {
+        let mut s = self.state_mut();
+
+        let mut history = s.history.clone();
+        if history.horizon.is_none() {
+            history.since = history.version;
+        }
+        history.horizon = horizon;
+
+        let mut batch = StoreBatch::default();
+        history.prune(self.db.as_ref(), &mut batch)?;
+        history.batch(&mut batch)?;
+        self.db.write(batch)?;
+
+        s.history = history;
+
+        Ok(())
+    }
+
+    /// Overlay of the reverted changes performed after the provided version
+    fn overlay_at(&self, version: u64) -> Result<TreeOverlay<'_, T>, Error> {
+        let snapshot = self.snapshot();
+        if !snapshot.state.history.retains(version) {
+            return Err(Error::VersionNotRetained(version));
+        }
+
+        // The first change of every index after the version holds its value in the version
+        let mut leaves = BTreeMap::new();
+        for change in History::changes_after(self.db.as_ref(), version) {
+            let (_, idx, leaf) = change?;
+
+            if let Entry::Vacant(entry) = leaves.entry(idx) {
+                let leaf: Option<T> = leaf
+                    .map(|l| bincode::deserialize(l.as_slice()))
+                    .transpose()
+                    .map_err(|e| Error::Other(e.to_string()))?;
+
+                entry.insert(leaf);
+            }
+        }
+
+        let mut overlay = TreeOverlay::from_snapshot(snapshot);
+        for (idx, leaf) in leaves {
+            match leaf {
+                Some(l) => overlay.insert(idx, l)?,
+                None => overlay.remove(idx)?,
+            }
+        }
+
+        Ok(overlay)
+    }
+
+    /// Calculate the root of the tree in the provided retained version
+    pub fn root_at(&self, version: u64) -> Result<T, Error> {
+        self.overlay_at(version)?.root()
+    }
+
+    /// Generate a proof of membership for the provided leaf index, relative to the root of the
+    /// provided retained version
+    pub fn proof_at(&self, version: u64, idx: usize) -> Result<BigProof<T>, Error> {
+        self.overlay_at(version)?.proof(idx)
+    }
+
+    /// Record the current value of the provided base indexes in the latest checkpoint, if they
+    /// were not yet modified since its creation.
+    ///
+    /// Only the newly recorded leaves are appended to the batch.
+    fn record_leaves(
+        &self,
+        s: &State<T>,
+        indexes: &[usize],
+        batch: &mut StoreBatch,
+    ) -> Result<(), Error> {
+        let checkpoint = match s.checkpoint.as_ref() {
+            Some(c) => c,
+            None => return Ok(()),
+        };
+
+        let mut recorded = HashSet::new();
+        for idx in indexes {
+            if recorded.insert(*idx) && !checkpoint.is_recorded(self.db(), *idx)? {
+                let coord: Vec<u8> = MerkleCoord::new(self.height, *idx).try_into()?;
+                checkpoint.batch_leaf(*idx, self.db.get(coord.as_slice())?, batch)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Retained checkpoints, from the oldest to the latest
+    pub fn checkpoints(&self) -> Vec<CheckpointId> {
+        self.state()
+            .checkpoints
+            .ids
+            .iter()
+            .map(|id| CheckpointId(*id))
+            .collect()
+    }
+
+    /// Change the maximum number of retained checkpoints, dropping the oldest ones that exceed it
+    pub fn set_checkpoint_capacity(&mut self, capacity: usize) -> Result<(), Error> {
+        let mut s = self.state_mut();
+
+        let mut checkpoints = s.checkpoints.clone();
+        let mut batch = StoreBatch::default();
+
+        checkpoints.capacity = capacity;
+        checkpoints.truncate(self.db(), &mut batch)?;
+        checkpoints.batch(&mut batch)?;
+        self.db.write(batch)?;
+
+        if checkpoints.ids.is_empty() {
+            s.checkpoint = None;
+        }
+        s.checkpoints = checkpoints;
+
+        Ok(())
+    }
+
+    /// Create a checkpoint of the current state of the tree.
+    ///
+    /// If the number of retained checkpoints exceeds the capacity, the oldest one is dropped.
+    pub fn checkpoint(&mut self) -> Result<CheckpointId, Error> {
+        let mut s = self.state_mut();
+
+        let mut checkpoints = s.checkpoints.clone();
+        let roots = bincode::serialize(&s.roots).map_err(|e| Error::Other(e.to_string()))?;
+        let checkpoint = Checkpoint::new(checkpoints.next, s.empty_intervals.clone(), roots);
+
+        let mut batch = StoreBatch::default();
+        checkpoint.batch(&mut batch)?;
+
+        checkpoints.next += 1;
+        checkpoints.ids.push_back(checkpoint.id);
+        checkpoints.truncate(self.db(), &mut batch)?;
+        checkpoints.batch(&mut batch)?;
+
+        self.write_version(&mut s, batch)?;
+
+        let id = CheckpointId(checkpoint.id);
+        s.checkpoint = Some(checkpoint).filter(|_| !checkpoints.ids.is_empty());
+        s.checkpoints = checkpoints;
+
+        Ok(id)
+    }
+
+    /// Restore the leaves, the empty intervals and the size of the tree to the provided
+    /// checkpoint, and invalidate the cached nodes above the restored leaves.
+    ///
+    /// The checkpoints created after the provided one are dropped, and so are the roots recorded
+    /// after it. Only the leaves written by the tree, as in [`BigMerkleTree::insert`] and
+    /// [`BigMerkleTree::remove`], are restored.
+    ///
+    /// Will fail with [`Error::RollbackUnsupported`] if the store of the tree holds the records
+    /// of another structure, as a [`BigIndexedStorage`].
+    ///
+    /// [`BigIndexedStorage`]: crate::BigIndexedStorage
+    pub fn rollback_to(&mut self, id: CheckpointId) -> Result<(), Error> {
+        let mut s = self.state_mut();
+        let s = &mut *s;
+
+        if self.db.get(EXTERNAL_RECORDS_KEY)?.is_some() {
+            return Err(Error::RollbackUnsupported);
+        }
+
+        let CheckpointId(id) = id;
+        let position = s
+            .checkpoints
+            .ids
+            .iter()
+            .position(|i| *i == id)
+            .ok_or(Error::CheckpointNotFound(id))?;
+
+        let mut checkpoints = s.checkpoints.clone();
+        let mut batch = StoreBatch::default();
+
+        // The older checkpoints hold the older values, so they are applied last
+        let mut leaves = BTreeMap::new();
+        let mut empty_intervals = None;
+        let mut roots = None;
+        while checkpoints.ids.len() > position {
+            let c = match checkpoints.ids.pop_back() {
+                Some(i) if Some(i) == s.checkpoint.as_ref().map(|c| c.id) => {
+                    s.checkpoint.clone().ok_or(Error::CheckpointNotFound(i))?
+                }
+                Some(i) => Checkpoint::fetch(self.db.as_ref(), i)?,
+                None => return Err(Error::CheckpointNotFound(id)),
+            };
+
+            for leaf in Checkpoint::leaves(self.db(), c.id) {
+                let (idx, leaf) = leaf?;
+                leaves.insert(idx, leaf);
+            }
+            Checkpoint::batch_delete(self.db(), c.id, &mut batch)?;
+            empty_intervals = Some(c.empty_intervals);
+            roots = Some(c.roots);
+        }
+        let empty_intervals = empty_intervals.ok_or(Error::CheckpointNotFound(id))?;
+        let roots = roots.ok_or(Error::CheckpointNotFound(id))?;
+        if leaves
+            .keys()
+            .any(|idx| self.is_pruned_in(s, self.height, *idx))
+        {
+            return Err(Error::LeafPruned);
+        }
+
+        for (idx, leaf) in leaves.iter() {
+            self.index_leaf(s, *idx, leaf.as_deref(), &mut batch)?;
+            let coord: Vec<u8> = MerkleCoord::new(self.height, *idx).try_into()?;
+
+            match leaf {
+                Some(l) => batch.put(coord, l),
+                None => batch.delete(coord),
+            }
+        }
+
+        // The roots recorded after the checkpoint were never reached by the restored tree
+        let restored: RootHistory<T> =
+            bincode::deserialize(roots.as_slice()).map_err(|e| Error::Other(e.to_string()))?;
+        batch.put(ROOT_HISTORY_KEY, roots.as_slice());
+
+        // The restored checkpoint is retained, with no modifications
+        let checkpoint = Checkpoint::new(id, empty_intervals.clone(), roots);
+        checkpoint.batch(&mut batch)?;
+        checkpoints.ids.push_back(id);
+        checkpoints.batch(&mut batch)?;
+
+        Metadata::batch_empty_diff(&s.empty_intervals, &empty_intervals, &mut batch);
+        let indexes: Vec<usize> = leaves.keys().cloned().collect();
+        self.modified(s, indexes.as_slice(), &mut batch)?;
+
+        // The rollback is a new version of the tree
+        let history = s.history.clone();
+        let written = self
+            .log_history(s, indexes.as_slice(), &mut batch)
+            .and_then(|_| self.db.write(batch));
+        if written.is_err() {
+            s.history = history;
+        }
+        written?;
+
+        s.empty_intervals = empty_intervals;
+        s.checkpoints = checkpoints;
+        s.checkpoint = Some(checkpoint);
+        s.roots = restored;
+
+        Ok(())
+    }
+
+    /// Flag the base indexes as modified, and delete all sub-trees from the cache
+    fn modified(
+        &self,
+        s: &State<T>,
+        indexes: &[usize],
+        batch: &mut StoreBatch,
+    ) -> Result<(), Error> {
+        let mut invalidated = HashSet::new();
+
+        for idx in indexes {
+            let mut coord = MerkleCoord::new(self.height, *idx);
+
+            loop {
+                coord.descend(1);
+
+                // The ancestors of this node were already invalidated
+                if !invalidated.insert((coord.height, coord.idx)) {
+                    break;
                 }
 
-                if r == &r2 {
-                    idx_right = Some(i);
+                let c: Vec<u8> = coord.try_into()?;
+                batch.delete(c);
+                s.cache.invalidate(coord.height, coord.idx);
+
+                if coord.height == 0 {
+                    break;
                 }
             }
+        }
+
+        Ok(())
+    }
+
+    /// Return the persisted history of roots.
+    ///
+    /// If no root was recorded, an empty history retaining up to 256 roots is returned.
+    pub fn root_history(&self) -> Result<RootHistory<T>, Error> {
+        Ok(self.state().roots.clone())
+    }
+
+    /// Persist the provided history of roots, and replace the history of the state once written
+    fn write_root_history(&self, s: &mut State<T>, roots: RootHistory<T>) -> Result<(), Error> {
+        let bytes = bincode::serialize(&roots).map_err(|e| Error::Other(e.to_string()))?;
+
+        let mut batch = StoreBatch::default();
+        batch.put(ROOT_HISTORY_KEY, bytes);
+        self.db.write(batch)?;
+
+        s.roots = roots;
+
+        Ok(())
+    }
+
+    /// Change the maximum number of roots retained by the persisted history
+    pub fn set_root_history_capacity(&mut self, capacity: usize) -> Result<(), Error> {
+        let mut s = self.state_mut();
+
+        let mut roots = s.roots.clone();
+        roots.set_capacity(capacity);
+
+        self.write_root_history(&mut s, roots)
+    }
+
+    /// Calculate the root of the tree, and record it in the persisted history as the root of the
+    /// provided epoch.
+    ///
+    /// The root is calculated and recorded under the write lock, so no mutation can happen in
+    /// between.
+    pub fn record_root(&mut self, epoch: u64) -> Result<T, Error> {
+        let mut s = self.state_mut();
+        let root = self.root_in(&s)?;
+
+        let mut roots = s.roots.clone();
+        roots.record(epoch, root)?;
+        let bytes = bincode::serialize(&roots).map_err(|e| Error::Other(e.to_string()))?;
+
+        let mut batch = StoreBatch::default();
+        batch.put(ROOT_HISTORY_KEY, bytes);
+        self.write_version(&mut s, batch)?;
+
+        s.roots = roots;
+
+        Ok(root)
+    }
+
+    /// Check if the provided root is one of the roots retained by the persisted history
+    pub fn is_known_root(&self, root: &T) -> Result<bool, Error> {
+        Ok(self.state().roots.is_known_root(root))
+    }
+
+    /// Latest epoch of the persisted history in which the provided root was current
+    pub fn root_epoch(&self, root: &T) -> Result<Option<u64>, Error> {
+        Ok(self.state().roots.epoch_of(root))
+    }
 
-            // If the interval is not found, then we have unreachable code since the
-            // `node_is_empty` check was performed
-            let idx_left = idx_left.ok_or(Error::IndexOutOfBounds)?;
-            let idx_right = idx_right.ok_or(Error::IndexOutOfBounds)?;
-
-            self.empty_intervals[idx_left].0.end = self.empty_intervals[idx_right].0.end;
-            self.empty_intervals.remove(idx_right);
-        } else if left {
-            // Decrement the left interval
-            let r1 = MerkleRange::from(idx - 1..idx);
-            let mut r1_idx = None;
-
-            for (i, r) in self.empty_intervals.iter().enumerate() {
-                if r == &r1 {
-                    r1_idx = Some(i);
+    /// Configuration of the node caching
+    pub fn cache_config(&self) -> CacheConfig {
+        *self.state().cache.config()
+    }
+
+    /// Replace the configuration of the node caching, discarding the nodes in memory
+    pub fn set_cache_config(&mut self, config: CacheConfig) {
+        self.state_mut().cache = NodeCache::new(config);
+    }
+
+    /// Counters of the in-memory node cache
+    pub fn cache_stats(&self) -> CacheStats {
+        self.state().cache.stats()
+    }
+
+    /// Discard the nodes cached in memory and, optionally, the nodes cached in the store
+    pub fn clear_cache(&mut self, disk: bool) -> Result<(), Error> {
+        let s = self.state_mut();
+        s.cache.clear();
+
+        if disk {
+            let mut batch = StoreBatch::default();
+            for height in 0..self.height {
+                let prefix = MerkleCoord::level_prefix(height);
+                for item in self.db.iter_prefix(&prefix) {
+                    batch.delete(item?.0);
                 }
             }
 
-            // If the interval is not found, then we have unreachable code since the
-            // `node_is_empty` check was performed
-            let r1_idx = r1_idx.ok_or(Error::IndexOutOfBounds)?;
+            self.db.write(batch)?;
+        }
 
-            // Decrement the range, if the number of elements is greater than 1
-            if self.empty_intervals[r1_idx].0.end == self.empty_intervals[r1_idx].0.start + 1 {
-                self.empty_intervals.remove(r1_idx);
-            } else {
-                self.empty_intervals[r1_idx].0.end -= 1;
+        Ok(())
+    }
+
+    /// Mark the provided leaf index to be retained by [`BigMerkleTree::prune`]
+    pub fn mark(&mut self, idx: usize) -> Result<(), Error> {
+        let mut s = self.state_mut();
+
+        if idx >= self.width {
+            return Err(Error::IndexOutOfBounds);
+        } else if self.is_pruned_in(&s, self.height, idx) {
+            return Err(Error::LeafPruned);
+        }
+
+        let mut pruning = s.pruning.clone();
+        pruning.marked.insert(idx);
+        self.persist_pruning(&mut s, pruning)
+    }
+
+    /// Allow the provided leaf index to be discarded by the next [`BigMerkleTree::prune`]
+    pub fn unmark(&mut self, idx: usize) -> Result<(), Error> {
+        let mut s = self.state_mut();
+
+        let mut pruning = s.pruning.clone();
+        pruning.marked.remove(&idx);
+        self.persist_pruning(&mut s, pruning)
+    }
+
+    /// Check if the provided leaf index is marked to be retained
+    pub fn is_marked(&self, idx: usize) -> bool {
+        self.state().pruning.marked.contains(&idx)
+    }
+
+    fn persist_pruning(&self, s: &mut State<T>, pruning: Pruning) -> Result<(), Error> {
+        let mut batch = StoreBatch::default();
+        pruning.batch(&mut batch)?;
+        self.db.write(batch)?;
+
+        s.pruning = pruning;
+
+        Ok(())
+    }
+
+    /// Check if the node in the provided height and index belongs to a pruned sub-tree
+    pub fn is_pruned(&self, height: usize, idx: usize) -> bool {
+        self.is_pruned_in(&self.state(), height, idx)
+    }
+
+    fn is_pruned_in(&self, s: &State<T>, height: usize, idx: usize) -> bool {
+        let r = MerkleRange::new(self.height, height, idx);
+        s.pruning.pruned.contains(&r.0)
+    }
+
+    /// Node of a pruned sub-tree, available only for the sub-trees that were pinned
+    fn pinned(&self, s: &State<T>, height: usize, idx: usize) -> Result<Option<T>, Error> {
+        if self.node_is_empty_in(s, height, idx) {
+            return Ok(self.empty[self.height - height]);
+        }
+
+        fetch_raw(self.db(), Pruning::pin_key(height, idx).as_slice())?
+            .map(Some)
+            .ok_or(Error::LeafPruned)
+    }
+
+    /// Discard every leaf that is not marked, keeping only the nodes required to calculate the
+    /// root, the proofs of the marked leaves and the appends after the last leaf.
+    ///
+    /// Every maximal sub-tree up to the last leaf without marked leaves is replaced by its pinned
+    /// node. The leaves of the pruned sub-trees can no longer be changed, or proven.
+    ///
+    /// The pruning is a new version of the tree, and the history starts over from it: the
+    /// previous versions can no longer be restored.
+    pub fn prune(&mut self) -> Result<(), Error> {
+        let mut s = self.state_mut();
+        if self.size_in(&s) == 0 {
+            return Ok(());
+        }
+
+        let mut pins = vec![];
+        self.prunable(&s, 0, 0, self.size_in(&s), &mut pins);
+
+        let mut pruning = s.pruning.clone();
+        let mut batch = StoreBatch::default();
+
+        for (height, idx) in pins {
+            let range = MerkleRange::new(self.height, height, idx).0;
+            if pruning.pruned.contains(&range) {
+                continue;
             }
-        } else if right {
-            // Increment the left start interval
-            let r1 = MerkleRange::from(idx + 1..idx + 2);
-            let mut r1_idx = None;
-
-            for (i, r) in self.empty_intervals.iter().enumerate() {
-                if r == &r1 {
-                    r1_idx = Some(i);
-                }
+
+            if !self.node_is_empty_in(&s, height, idx) {
+                let node = self.node_in(&s, height, idx)?;
+                let node = bincode::serialize(&node.ok_or(Error::LeafPruned)?)
+                    .map_err(|e| Error::Other(e.to_string()))?;
+
+                batch.put(Pruning::pin_key(height, idx), node);
             }
 
-            // If the interval is not found, then we have unreachable code since the
-            // `node_is_empty` check was performed
-            let r1_idx = r1_idx.ok_or(Error::IndexOutOfBounds)?;
-            self.empty_intervals[r1_idx].0.start += 1;
+            // Discard the leaves, the cached nodes and the previous pins of the sub-tree
+            for h in height..self.height + 1 {
+                let levels = MERKLE_ARITY.pow((self.height - h) as u32);
 
-            // Increments the range, if the number of elements is greater than 1
-            if self.empty_intervals[r1_idx].0.end == self.empty_intervals[r1_idx].0.start + 1 {
-                self.empty_intervals.remove(r1_idx);
-            } else {
-                self.empty_intervals[r1_idx].0.start += 1;
+                for i in range.start / levels..range.end / levels {
+                    if h == self.height {
+                        self.index_leaf(&s, i, None, &mut batch)?;
+                    }
+
+                    let coord: Vec<u8> = MerkleCoord::new(h, i).try_into()?;
+                    batch.delete(coord);
+
+                    if h > height {
+                        batch.delete(Pruning::pin_key(h, i));
+                    }
+                }
             }
-        } else {
-            // If there is no adjacent empty interval, then create an interval of its own
-            self.empty_intervals.push((idx..idx + 1).into());
+
+            pruning.pruned.insert(range);
+        }
+
+        pruning.batch(&mut batch)?;
+
+        // The previous versions would be restored over the discarded leaves
+        let history = s.history.clone();
+        s.history.since = s.history.version + 1;
+        if let Err(e) = self.write_version(&mut s, batch) {
+            s.history = history;
+            return Err(e);
         }
 
-        self.modified(idx)
+        s.pruning = pruning;
+
+        Ok(())
     }
 
-    /// Flag the base idx as modified, and delete all sub-trees from the cache
-    fn modified(&mut self, idx: usize) -> Result<(), Error> {
-        let mut coord = MerkleCoord::new(self.height, idx);
+    /// Collect the maximal sub-trees before the provided frontier without marked leaves
+    fn prunable(
+        &self,
+        s: &State<T>,
+        height: usize,
+        idx: usize,
+        frontier: usize,
+        pins: &mut Vec<(usize, usize)>,
+    ) {
+        let range = MerkleRange::new(self.height, height, idx).0;
+
+        if range.start >= frontier {
+            return;
+        } else if range.end <= frontier && s.pruning.marked.range(range).next().is_none() {
+            pins.push((height, idx));
+            return;
+        } else if height == self.height {
+            return;
+        }
 
-        loop {
-            coord.descend(1);
+        for i in 0..MERKLE_ARITY {
+            self.prunable(s, height + 1, idx * MERKLE_ARITY + i, frontier, pins);
+        }
+    }
 
-            let c: Vec<u8> = coord.try_into()?;
-            self.cache
-                .delete(c.as_slice())
-                .map_err(|e| Error::Other(e.to_string()))?;
+    /// Maintain, or discard, the secondary index of the leaves by value in the store, required
+    /// by [`BigMerkleTree::find`].
+    ///
+    /// Enabling the index will index every present leaf of the tree.
+    pub fn set_leaf_index(&mut self, enabled: bool) -> Result<(), Error> {
+        let mut s = self.state_mut();
+        if s.leaf_index.enabled == enabled {
+            return Ok(());
+        }
 
-            if coord.height == 0 {
-                break;
+        let mut batch = StoreBatch::default();
+        if enabled {
+            let prefix = MerkleCoord::level_prefix(self.height);
+            for item in self.db.iter_prefix(&prefix) {
+                let (k, v) = item?;
+                if let Ok(c) = MerkleCoord::try_from(k.as_slice()) {
+                    LeafIndex::update(None, Some(v.as_slice()), c.idx, &mut batch);
+                }
             }
+        } else {
+            LeafIndex::clear(self.db.as_ref(), &mut batch)?;
+        }
+
+        let leaf_index = LeafIndex { enabled };
+        leaf_index.batch(&mut batch)?;
+        self.write_version(&mut s, batch)?;
+
+        s.leaf_index = leaf_index;
+
+        Ok(())
+    }
+
+    /// Check if the leaves are indexed by value
+    pub fn has_leaf_index(&self) -> bool {
+        self.state().leaf_index.enabled
+    }
+
+    /// Replace the entry of the previous leaf of the provided index, if the leaves are indexed
+    fn index_leaf(
+        &self,
+        s: &State<T>,
+        idx: usize,
+        leaf: Option<&[u8]>,
+        batch: &mut StoreBatch,
+    ) -> Result<(), Error> {
+        if !s.leaf_index.enabled {
+            return Ok(());
         }
 
+        let coord: Vec<u8> = MerkleCoord::new(self.height, idx).try_into()?;
+        let previous = self.db.get(coord.as_slice())?;
+        LeafIndex::update(previous.as_deref(), leaf, idx, batch);
+
         Ok(())
     }
 
+    /// Indexes of the provided leaf, in ascending order.
+    ///
+    /// Will fail with [`Error::LeafIndexDisabled`] if the leaves are not indexed.
+    pub fn find(&self, leaf: &T) -> Result<Vec<usize>, Error> {
+        self.find_in(&self.state(), leaf)
+    }
+
+    fn find_in(&self, s: &State<T>, leaf: &T) -> Result<Vec<usize>, Error> {
+        if !s.leaf_index.enabled {
+            return Err(Error::LeafIndexDisabled);
+        }
+
+        let leaf = bincode::serialize(leaf).map_err(|e| Error::Other(e.to_string()))?;
+        LeafIndex::find(self.db.as_ref(), leaf.as_slice())
+    }
+
+    /// Check if the provided leaf is present in the tree
+    pub fn contains(&self, leaf: &T) -> Result<bool, Error> {
+        self.find(leaf).map(|indexes| !indexes.is_empty())
+    }
+
+    /// Generate a proof of membership for the lowest index of the provided leaf
+    pub fn proof_for_leaf(&self, leaf: &T) -> Result<BigProof<T>, Error> {
+        let s = self.state();
+
+        let idx = self
+            .find_in(&s, leaf)?
+            .first()
+            .copied()
+            .ok_or(Error::LeafNotFound)?;
+
+        self.proof_in(&s, idx)
+    }
+
     /// Fetch a node of the tree for the provided coordinates
-    pub fn node<T: PoseidonLeaf>(&mut self, height: usize, idx: usize) -> Result<Option<T>, Error>
-    where
-        Scalar: ops::Mul<T, Output = T>,
-    {
+    pub fn node(&self, height: usize, idx: usize) -> Result<Option<T>, Error> {
+        self.node_in(&self.state(), height, idx)
+    }
+
+    fn node_in(&self, s: &State<T>, height: usize, idx: usize) -> Result<Option<T>, Error> {
+        if self.is_pruned_in(s, height, idx) {
+            return self.pinned(s, height, idx);
+        }
+
         if height == self.height {
             // Fetch directly from db
-            MerkleCoord::new(height, idx).fetch_leaf(&self.db)
-        } else if self.node_is_empty(height, idx) {
+            MerkleCoord::new(height, idx).fetch_leaf(self.db.as_ref())
+        } else if self.node_is_empty_in(s, height, idx) {
             // Fetch a precalculated null node
-            if height == self.height {
-                Ok(None)
-            } else {
-                // TODO Generate a precalculated height for null sub-trees
-                Ok(Some(T::from(0u64)))
-            }
+            Ok(self.empty[self.height - height])
         } else {
+            if let Some(node) = s.cache.get(height, idx) {
+                return Ok(Some(node));
+            }
+
             // Calculate the node
             let coord = MerkleCoord::new(height, idx);
-            let should_cache = (height % CACHE_HEIGHT_INTERVAL) == 0;
+            let config = *s.cache.config();
 
-            let node = if should_cache {
-                coord.fetch_leaf::<T>(&self.cache)?
+            let node = if config.reads(height) {
+                coord.fetch_leaf(self.db.as_ref())?
             } else {
                 None
             };
 
-            if node.is_some() {
+            if let Some(n) = node {
+                s.cache.insert(height, idx, n);
                 return Ok(node);
             }
 
@@ -345,26 +1426,32 @@ impl BigMerkleTree {
 
             let needle = idx * MERKLE_ARITY;
             for i in 0..MERKLE_ARITY {
-                if let Some(n) = self.node(height + 1, needle + i)? {
+                if let Some(n) = self.node_in(s, height + 1, needle + i)? {
                     h.insert_unchecked(i, n);
                 }
             }
 
             let node = h.hash();
-            if should_cache {
-                coord.persist_leaf(&self.cache, node)?;
+            if config.persists(height) {
+                // The readers only hold the read lock, so the persistence is best-effort. The
+                // written node is determined by the locked version, and every writer, including
+                // the cache invalidation, holds the write lock, so concurrent readers can only
+                // write the same value.
+                coord.persist_leaf(self.db.as_ref(), node).ok();
             }
+            s.cache.insert(height, idx, node);
 
             Ok(Some(node))
         }
     }
 
     /// Generate a proof of membership for the provided leaf index
-    pub fn proof<T: PoseidonLeaf>(&mut self, mut needle: usize) -> Result<BigProof<T>, Error>
-    where
-        Scalar: ops::Mul<T, Output = T>,
-    {
-        let mut proof = BigProof::new();
+    pub fn proof(&self, needle: usize) -> Result<BigProof<T>, Error> {
+        self.proof_in(&self.state(), needle)
+    }
+
+    fn proof_in(&self, s: &State<T>, mut needle: usize) -> Result<BigProof<T>, Error> {
+        let mut proof = BigProof::with_height(self.height);
         let mut leaves = [None; MERKLE_ARITY];
 
         for row in 0..self.height {
@@ -372,7 +1459,7 @@ impl BigMerkleTree {
             let idx = needle % MERKLE_ARITY;
 
             for i in 0..MERKLE_ARITY {
-                leaves[i] = self.node(self.height - row, from + i)?;
+                leaves[i] = self.node_in(s, self.height - row, from + i)?;
             }
 
             proof.push(idx, leaves);
@@ -382,44 +1469,51 @@ impl BigMerkleTree {
         Ok(proof)
     }
 
-    /// Calculate and return the root of the merkle tree.
-    pub fn root<T: PoseidonLeaf>(&mut self) -> Result<T, Error>
-    where
-        Scalar: ops::Mul<T, Output = T>,
-    {
-        let (tx, rx) = mpsc::channel();
-        let rx = Mutex::new(rx);
-        let rx = Arc::new(rx);
-
-        let mut handles: Vec<thread::JoinHandle<Result<(), Error>>> = vec![];
+    /// Generate a proof that the current tree only appended leaves to the tree of the provided
+    /// size.
+    pub fn consistency_proof(&self, old_size: usize) -> Result<ConsistencyProof<T>, Error> {
+        let s = self.state();
 
-        let segments = self.segments();
-        for s in segments {
-            tx.send(s).map_err(|e| Error::Other(e.to_string()))?;
+        let new_size = self.size_in(&s);
+        if old_size > new_size {
+            return Err(Error::IndexOutOfBounds);
         }
 
-        for _ in 0..num_cpus::get() {
-            let worker = Arc::clone(&rx);
-            let mut tree = self.clone();
+        let old_path = match old_size {
+            0 => vec![],
+            _ => self.proof_in(&s, old_size - 1)?.data().clone(),
+        };
 
-            handles.push(thread::spawn(move || {
-                while let Some(c) = worker
-                    .lock()
-                    .map(|r| r.recv().ok())
-                    .map_err(|e| Error::Other(e.to_string()))?
-                {
-                    tree.node(c.height, c.idx)?;
-                }
+        let new_path = match new_size {
+            0 => vec![],
+            _ => self.proof_in(&s, new_size - 1)?.data().clone(),
+        };
 
-                Ok(())
-            }));
-        }
+        Ok(ConsistencyProof::new(old_path, new_path))
+    }
 
-        for h in handles {
-            h.join().unwrap().unwrap();
-        }
+    /// Calculate and return the root of the merkle tree.
+    ///
+    /// The cached segments of the tree are evaluated in parallel by [`threads`] workers. The
+    /// first storage error is returned, and the remaining segments are discarded.
+    ///
+    /// [`threads`]: BigMerkleTree::threads
+    pub fn root(&self) -> Result<T, Error> {
+        self.root_in(&self.state())
+    }
+
+    fn root_in(&self, s: &State<T>) -> Result<T, Error> {
+        let segments = self
+            .segments_in(s)
+            .into_iter()
+            .filter(|c| !self.is_pruned_in(s, c.height, c.idx))
+            .collect();
 
-        self.node(0, 0).and_then(|n| {
+        pool::execute(segments, self.threads, |c| {
+            self.node_in(s, c.height, c.idx).map(|_| ())
+        })?;
+
+        self.node_in(s, 0, 0).and_then(|n| {
             n.ok_or(Error::Other(
                 "It was not possible to obtain the root node from the merkle tree.".to_owned(),
             ))
@@ -428,39 +1522,402 @@ impl BigMerkleTree {
 }
 
 #[cfg(test)]
-pub fn big_merkle_default(path: &str) -> BigMerkleTree {
+pub fn big_merkle_default(path: &str) -> BigMerkleTree<Scalar> {
     // 2^34
     let width = 17179869184;
     let db_path = TempDir::new(path).map(|t| t.into_path()).unwrap();
 
-    let cache_path = format!("{}-cache", path);
-    let cache_path = TempDir::new(cache_path.as_str())
-        .map(|t| t.into_path())
-        .unwrap();
+    BigMerkleTree::create(db_path, width).unwrap()
+}
+
+/// Same tree of [`big_merkle_default`], for every storage backend shipped with the crate
+#[cfg(test)]
+pub fn big_merkle_stores(path: &str) -> Vec<BigMerkleTree<Scalar>> {
+    let rocks = big_merkle_default(path);
+    let memory =
+        BigMerkleTree::with_store(Arc::new(MemoryStore::default()), rocks.width()).unwrap();
 
-    BigMerkleTree::new(db_path, cache_path, width).unwrap()
+    vec![rocks, memory]
+}
+
+/// Store that can simulate an interruption of the process, discarding the writes, or an
+/// unavailable backend, failing the reads. The size, in bytes, of the last written batch is
+/// recorded.
+#[cfg(test)]
+#[derive(Debug, Default)]
+pub struct InterruptedStore {
+    pub store: MemoryStore,
+    pub interrupted: AtomicBool,
+    pub unavailable: AtomicBool,
+    pub written: AtomicUsize,
+}
+
+#[cfg(test)]
+impl MerkleStore for InterruptedStore {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+        if self.unavailable.load(Ordering::SeqCst) {
+            return Err(Error::Other("Unavailable".to_owned()));
+        }
+
+        self.store.get(key)
+    }
+
+    fn put(&self, key: &[u8], value: &[u8]) -> Result<(), Error> {
+        if self.interrupted.load(Ordering::SeqCst) {
+            return Err(Error::Other("Interrupted".to_owned()));
+        }
+
+        self.store.put(key, value)
+    }
+
+    fn delete(&self, key: &[u8]) -> Result<(), Error> {
+        if self.interrupted.load(Ordering::SeqCst) {
+            return Err(Error::Other("Interrupted".to_owned()));
+        }
+
+        self.store.delete(key)
+    }
+
+    fn write(&self, batch: StoreBatch) -> Result<(), Error> {
+        if self.interrupted.load(Ordering::SeqCst) {
+            return Err(Error::Other("Interrupted".to_owned()));
+        }
+
+        let size = batch
+            .ops()
+            .iter()
+            .map(|(k, v)| k.len() + v.as_ref().map(|v| v.len()).unwrap_or(0))
+            .sum();
+        self.written.store(size, Ordering::SeqCst);
+        self.store.write(batch)
+    }
+
+    fn iter_from<'a>(&'a self, key: &[u8], direction: IterDirection) -> StoreIter<'a> {
+        self.store.iter_from(key, direction)
+    }
+}
+
+/// Tree with the width of [`MerkleTree`], for every storage backend shipped with the crate
+///
+/// [`MerkleTree`]: crate::MerkleTree
+#[cfg(test)]
+pub fn big_merkle_small(path: &str) -> Vec<BigMerkleTree<Scalar>> {
+    let db_path = TempDir::new(path).map(|t| t.into_path()).unwrap();
+
+    let rocks = BigMerkleTree::create(db_path, MERKLE_WIDTH).unwrap();
+    let memory = BigMerkleTree::with_store(Arc::new(MemoryStore::default()), MERKLE_WIDTH).unwrap();
+
+    vec![rocks, memory]
 }
 
 #[cfg(test)]
 mod tests {
-    use super::big_merkle_default;
+    use super::{big_merkle_small, big_merkle_stores, InterruptedStore};
+    use crate::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+    use std::convert::TryFrom;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+    use tempdir::TempDir;
 
     #[test]
     fn big_merkle_empty() {
-        let mut merkle = big_merkle_default("big_merkle_empty");
-        let idx = merkle.width() / 3;
+        for mut merkle in big_merkle_stores("big_merkle_empty") {
+            let idx = merkle.width() / 3;
 
-        assert!(merkle.node_is_empty(0, 0));
-        assert!(merkle.node_is_empty(merkle.height(), idx));
+            assert!(merkle.node_is_empty(0, 0));
+            assert!(merkle.node_is_empty(merkle.height(), idx));
 
-        merkle.inserted(idx).unwrap();
+            merkle.inserted(idx).unwrap();
 
-        assert!(!merkle.node_is_empty(0, 0));
-        assert!(!merkle.node_is_empty(merkle.height(), idx));
-        assert!(merkle.node_is_empty(merkle.height(), idx - 1));
-        assert!(merkle.node_is_empty(merkle.height(), idx + 1));
+            assert!(!merkle.node_is_empty(0, 0));
+            assert!(!merkle.node_is_empty(merkle.height(), idx));
+            assert!(merkle.node_is_empty(merkle.height(), idx - 1));
+            assert!(merkle.node_is_empty(merkle.height(), idx + 1));
 
-        merkle.inserted(0).unwrap();
-        assert!(!merkle.node_is_empty(merkle.height(), 0));
+            merkle.inserted(0).unwrap();
+            assert!(!merkle.node_is_empty(merkle.height(), 0));
+        }
+    }
+
+    #[test]
+    fn big_merkle_root_history() {
+        for mut merkle in big_merkle_stores("big_merkle_root_history") {
+            merkle.set_root_history_capacity(2).unwrap();
+
+            let mut roots = vec![];
+            for i in 0..3 {
+                merkle.insert(i, Scalar::from(i as u64)).unwrap();
+                roots.push(merkle.record_root(100 + i as u64).unwrap());
+            }
+
+            assert!(!merkle.is_known_root(&roots[0]).unwrap());
+            assert!(merkle.is_known_root(&roots[1]).unwrap());
+            assert_eq!(Some(102), merkle.root_epoch(&roots[2]).unwrap());
+
+            // The history is persisted in the store of the tree
+            let merkle = BigMerkleTree::<Scalar>::open_store(Arc::clone(&merkle.db)).unwrap();
+            let history = merkle.root_history().unwrap();
+            assert_eq!(2, history.capacity());
+            assert_eq!(Some((102, roots[2])), history.latest());
+        }
+    }
+
+    #[test]
+    fn big_merkle_matches_merkle_tree() {
+        for mut big in big_merkle_small("big_merkle_matches_merkle_tree") {
+            let mut t = MerkleTree::<Scalar>::default();
+            assert_eq!(MERKLE_HEIGHT, big.height());
+            assert_eq!(t.root(), big.root().unwrap());
+
+            for i in (0..MERKLE_WIDTH / 2).step_by(3) {
+                big.insert(i, Scalar::from(i as u64)).unwrap();
+                t.insert_unchecked(i, Scalar::from(i as u64));
+            }
+            big.remove(6).unwrap();
+            t.remove_unchecked(6);
+
+            let root = t.root();
+            assert_eq!(root, big.root().unwrap());
+
+            for i in vec![0, 3, 9, 30] {
+                let leaf = Scalar::from(i as u64);
+                let proof = t.proof_index(i);
+                let big_proof = big.proof(i).unwrap();
+
+                assert_eq!(&proof.data()[..], &big_proof.data()[..]);
+                assert!(proof.verify_at(i, &leaf, &big.root().unwrap()));
+                assert!(big_proof.verify_at(i, &leaf, &root));
+            }
+        }
+    }
+
+    #[test]
+    fn big_merkle_random_removals() {
+        let mut rng = StdRng::seed_from_u64(0x6269_676d);
+
+        for mut big in big_merkle_small("big_merkle_random_removals") {
+            let mut t = MerkleTree::<Scalar>::default();
+
+            for _ in 0..64 {
+                let idx = rng.gen_range(0, MERKLE_WIDTH);
+
+                if rng.gen_bool(0.6) {
+                    big.insert(idx, Scalar::from(idx as u64)).unwrap();
+                    t.insert_unchecked(idx, Scalar::from(idx as u64));
+                } else {
+                    big.remove(idx).unwrap();
+                    t.remove_unchecked(idx);
+                }
+
+                for i in 0..MERKLE_WIDTH {
+                    assert_eq!(t.leaves()[i].is_none(), big.node_is_empty(big.height(), i));
+                }
+            }
+
+            assert_eq!(t.root(), big.root().unwrap());
+        }
+    }
+
+    #[test]
+    fn big_merkle_reopen() {
+        let db_path = TempDir::new("big_merkle_reopen")
+            .map(|t| t.into_path())
+            .unwrap();
+
+        let root = {
+            let mut t = BigMerkleTree::create(&db_path, MERKLE_WIDTH).unwrap();
+            for i in (0..MERKLE_WIDTH).step_by(5) {
+                t.insert(i, Scalar::from(i as u64)).unwrap();
+            }
+            t.remove(10).unwrap();
+
+            t.root().unwrap()
+        };
+
+        let t = BigMerkleTree::<Scalar>::open(&db_path).unwrap();
+        assert_eq!(MERKLE_WIDTH, t.width());
+        assert_eq!((MERKLE_WIDTH - 1) / 5 * 5 + 1, t.size());
+        assert!(t.node_is_empty(t.height(), 10));
+        assert_eq!(root, t.root().unwrap());
+        drop(t);
+
+        // The legacy constructor ignores the path of the cache
+        let cache_path = db_path.join("cache");
+        #[allow(deprecated)]
+        let t = BigMerkleTree::<Scalar>::new(&db_path, &cache_path, MERKLE_WIDTH).unwrap();
+        assert_eq!(root, t.root().unwrap());
+        assert!(!cache_path.exists());
+        drop(t);
+
+        match BigMerkleTree::<Scalar>::create(&db_path, MERKLE_WIDTH * MERKLE_ARITY) {
+            Err(Error::MetadataMismatch {
+                parameter: "width", ..
+            }) => (),
+            _ => panic!("The tree was reopened with a different width"),
+        }
+
+        let db_path = TempDir::new("big_merkle_reopen_empty")
+            .map(|t| t.into_path())
+            .unwrap();
+        assert!(BigMerkleTree::<Scalar>::open(&db_path).is_err());
+    }
+
+    #[test]
+    fn big_merkle_insert_batch() {
+        let t1 = big_merkle_stores("big_merkle_insert_batch_1");
+        let t2 = big_merkle_stores("big_merkle_insert_batch_2");
+
+        for (mut t1, mut t2) in t1.into_iter().zip(t2) {
+            let leaves: Vec<(usize, Scalar)> = (0..40)
+                .chain(50..60)
+                .chain(vec![3, 7])
+                .enumerate()
+                .map(|(i, idx)| (idx, Scalar::from(i as u64)))
+                .collect();
+
+            leaves
+                .iter()
+                .for_each(|(idx, leaf)| t1.insert(*idx, *leaf).unwrap());
+            t2.insert_batch(leaves).unwrap();
+
+            assert_eq!(t1.size(), t2.size());
+            assert!(t2.node_is_empty(t2.height(), 45));
+            assert!(!t2.node_is_empty(t2.height(), 55));
+            assert_eq!(t1.root().unwrap(), t2.root().unwrap());
+
+            // A batch with an invalid index is entirely rejected
+            let root = t2.root().unwrap();
+            let width = t2.width();
+            assert!(t2
+                .insert_batch(vec![(45, Scalar::one()), (width, Scalar::one())])
+                .is_err());
+            assert!(t2.node_is_empty(t2.height(), 45));
+            assert_eq!(root, t2.root().unwrap());
+        }
+    }
+
+    #[test]
+    fn big_merkle_interrupted_write() {
+        let store = Arc::new(InterruptedStore::default());
+        let mut t = BigMerkleTree::with_store(store.clone(), MERKLE_WIDTH).unwrap();
+        let mut reference = MerkleTree::<Scalar>::default();
+
+        for i in 0..20 {
+            t.insert(i, Scalar::from(i as u64)).unwrap();
+            reference.insert_unchecked(i, Scalar::from(i as u64));
+        }
+
+        // The root calculation will populate the cached nodes
+        let root = t.root().unwrap();
+        assert_eq!(reference.root(), root);
+
+        // The leaf and the invalidation of the cached nodes are a single write, so an
+        // interruption cannot persist one without the other
+        store.interrupted.store(true, Ordering::SeqCst);
+        assert!(t.insert(3, Scalar::zero()).is_err());
+        assert!(t.remove(5).is_err());
+        assert!(!t.node_is_empty(t.height(), 5));
+        assert_eq!(root, t.root().unwrap());
+
+        // A restarted process will find a consistent tree
+        store.interrupted.store(false, Ordering::SeqCst);
+        let mut t = BigMerkleTree::<Scalar>::open_store(store.clone()).unwrap();
+        assert_eq!(root, t.root().unwrap());
+
+        t.insert(3, Scalar::zero()).unwrap();
+        reference.insert_unchecked(3, Scalar::zero());
+        assert_eq!(reference.root(), t.root().unwrap());
+
+        // The reads persist the cached nodes on a best-effort basis, so they do not fail with the
+        // writes of the store
+        t.clear_cache(true).unwrap();
+        store.interrupted.store(true, Ordering::SeqCst);
+        assert_eq!(reference.root(), t.snapshot().root().unwrap());
+        assert!(t.proof(3).is_ok());
+    }
+
+    #[test]
+    fn big_merkle_empty_intervals_writes() {
+        let store = Arc::new(InterruptedStore::default());
+        let mut t = BigMerkleTree::with_store(store.clone(), MERKLE_WIDTH).unwrap();
+
+        // Only the modified intervals are written, regardless of the number of intervals
+        t.insert(0, Scalar::one()).unwrap();
+        t.insert(MERKLE_WIDTH / 2, Scalar::one()).unwrap();
+        let written = store.written.load(Ordering::SeqCst);
+
+        for i in (2..MERKLE_WIDTH / 2).step_by(2) {
+            t.insert(i, Scalar::one()).unwrap();
+        }
+        t.insert(MERKLE_WIDTH / 2 + 2, Scalar::one()).unwrap();
+        assert_eq!(written, store.written.load(Ordering::SeqCst));
+
+        let t = BigMerkleTree::<Scalar>::open_store(store).unwrap();
+        assert!(t.node_is_empty(t.height(), 1));
+        assert!(!t.node_is_empty(t.height(), 2));
+        assert!(t.node_is_empty(t.height(), MERKLE_WIDTH / 2 + 1));
+        assert!(!t.node_is_empty(t.height(), MERKLE_WIDTH / 2 + 2));
+        assert!(t.node_is_empty(t.height(), MERKLE_WIDTH - 1));
+    }
+
+    #[test]
+    fn big_merkle_root_errors() {
+        let store = Arc::new(InterruptedStore::default());
+        let mut t = BigMerkleTree::with_store(store.clone(), MERKLE_WIDTH).unwrap();
+        let mut reference = MerkleTree::<Scalar>::default();
+
+        for i in 0..MERKLE_WIDTH {
+            t.insert(i, Scalar::from(i as u64)).unwrap();
+            reference.insert_unchecked(i, Scalar::from(i as u64));
+        }
+
+        // A failing worker must not panic the caller, regardless of the number of workers
+        store.unavailable.store(true, Ordering::SeqCst);
+        for threads in vec![0, 1, 4] {
+            t.set_threads(threads);
+            assert!(t.threads() > 0);
+            assert!(t.root().is_err());
+        }
+
+        store.unavailable.store(false, Ordering::SeqCst);
+        assert_eq!(reference.root(), t.root().unwrap());
+    }
+
+    #[test]
+    fn big_merkle_iter_level() {
+        for mut t in big_merkle_small("big_merkle_iter_level") {
+            for i in vec![40, 9, 2, 17, 3] {
+                t.insert(i, Scalar::from(i as u64)).unwrap();
+            }
+            t.remove(3).unwrap();
+
+            let leaves = |iter: &mut dyn Iterator<Item = Result<(usize, Scalar), Error>>| {
+                iter.map(|l| l.unwrap().0).collect::<Vec<usize>>()
+            };
+
+            let height = t.height();
+            assert_eq!(
+                vec![2, 9, 17, 40],
+                leaves(&mut t.iter_level(height, 0..MERKLE_WIDTH))
+            );
+            assert_eq!(vec![9, 17], leaves(&mut t.iter_level(height, 3..40)));
+            assert_eq!(vec![2], leaves(&mut t.iter_subtree(height - 1, 0)));
+            assert_eq!(vec![17], leaves(&mut t.iter_subtree(1, 1)));
+
+            // The cached nodes are persisted level by level
+            t.root().unwrap();
+            let cached: Vec<(usize, Scalar)> = t
+                .iter_level(2, 0..MERKLE_ARITY * MERKLE_ARITY)
+                .map(|n| n.unwrap())
+                .collect();
+            assert!(!cached.is_empty());
+            assert!(cached.windows(2).all(|w| w[0].0 < w[1].0));
+            for (idx, node) in cached.iter() {
+                assert_eq!(Some(*node), t.node(2, *idx).unwrap());
+            }
+        }
     }
 }
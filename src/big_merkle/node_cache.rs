@@ -0,0 +1,298 @@
+use std::collections::{BTreeMap, HashMap};
+use std::mem;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+/// Configuration of the node caching of a [`BigMerkleTree`].
+///
+/// [`BigMerkleTree`]: crate::BigMerkleTree
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct CacheConfig {
+    /// Approximate number of bytes used by the in-memory LRU of nodes
+    pub memory_budget: usize,
+    /// Number of levels, starting from the root, with the nodes pinned in memory
+    pub pinned_levels: usize,
+    /// Interval of the heights with the nodes cached in the store. Zero disables the cache in the
+    /// store.
+    pub disk_interval: usize,
+    /// Persist the calculated nodes in the store. Without it, the nodes already cached in the
+    /// store are still read.
+    pub disk_persist: bool,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        CacheConfig {
+            memory_budget: 16 * 1024 * 1024,
+            pinned_levels: 4,
+            disk_interval: 2,
+            disk_persist: true,
+        }
+    }
+}
+
+impl CacheConfig {
+    /// Check if the nodes of the provided height should be read from the cache in the store
+    pub fn reads(&self, height: usize) -> bool {
+        self.disk_interval > 0 && height % self.disk_interval == 0
+    }
+
+    /// Check if the nodes of the provided height should be persisted in the store
+    pub fn persists(&self, height: usize) -> bool {
+        self.disk_persist && self.reads(height)
+    }
+}
+
+/// Counters of the in-memory node cache
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Number of nodes found in memory
+    pub hits: u64,
+    /// Number of nodes not found in memory
+    pub misses: u64,
+    /// Number of nodes in the LRU
+    pub entries: usize,
+    /// Number of pinned nodes
+    pub pinned: usize,
+}
+
+#[derive(Debug)]
+struct State<T> {
+    pinned: HashMap<(usize, usize), T>,
+    /// Nodes of the LRU, and the tick of their last access
+    entries: HashMap<(usize, usize), (T, u64)>,
+    /// Nodes of the LRU, from the least recently used
+    order: BTreeMap<u64, (usize, usize)>,
+    tick: u64,
+}
+
+/// In-memory cache of the nodes of a tree, indexed by height and index.
+///
+/// The nodes of the top levels are never evicted, and the others are evicted in least recently
+/// used order once the memory budget is exceeded.
+#[derive(Debug)]
+pub(crate) struct NodeCache<T> {
+    config: CacheConfig,
+    capacity: usize,
+    state: Mutex<State<T>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl<T: Copy> NodeCache<T> {
+    /// Empty cache with the provided configuration
+    pub fn new(config: CacheConfig) -> Self {
+        // Both the map and the order index hold the coordinates of every entry
+        let entry = mem::size_of::<T>() + 2 * mem::size_of::<(usize, usize)>() + 16;
+
+        NodeCache {
+            config,
+            capacity: config.memory_budget / entry,
+            state: Mutex::new(State {
+                pinned: HashMap::new(),
+                entries: HashMap::new(),
+                order: BTreeMap::new(),
+                tick: 0,
+            }),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Configuration of the cache
+    pub fn config(&self) -> &CacheConfig {
+        &self.config
+    }
+
+    /// Fetch a node from memory, refreshing its position in the LRU
+    pub fn get(&self, height: usize, idx: usize) -> Option<T> {
+        let node = self.state.lock().ok().and_then(|mut s| {
+            if let Some(n) = s.pinned.get(&(height, idx)) {
+                return Some(*n);
+            }
+
+            let tick = s.tick + 1;
+            let previous = match s.entries.get_mut(&(height, idx)) {
+                Some((_, t)) => mem::replace(t, tick),
+                None => return None,
+            };
+
+            s.tick = tick;
+            s.order.remove(&previous);
+            s.order.insert(tick, (height, idx));
+            s.entries.get(&(height, idx)).map(|(n, _)| *n)
+        });
+
+        match node {
+            Some(_) => self.hits.fetch_add(1, Ordering::Relaxed),
+            None => self.misses.fetch_add(1, Ordering::Relaxed),
+        };
+
+        node
+    }
+
+    /// Keep a node in memory, evicting the least recently used nodes above the budget
+    pub fn insert(&self, height: usize, idx: usize, node: T) {
+        let mut s = match self.state.lock() {
+            Ok(s) => s,
+            Err(_) => return,
+        };
+
+        if height < self.config.pinned_levels {
+            s.pinned.insert((height, idx), node);
+            return;
+        } else if self.capacity == 0 {
+            return;
+        }
+
+        s.tick += 1;
+        let tick = s.tick;
+        if let Some((_, previous)) = s.entries.insert((height, idx), (node, tick)) {
+            s.order.remove(&previous);
+        }
+        s.order.insert(tick, (height, idx));
+
+        while s.entries.len() > self.capacity {
+            let oldest = match s.order.keys().next() {
+                Some(t) => *t,
+                None => break,
+            };
+
+            if let Some(coord) = s.order.remove(&oldest) {
+                s.entries.remove(&coord);
+            }
+        }
+    }
+
+    /// Discard a node from memory
+    pub fn invalidate(&self, height: usize, idx: usize) {
+        if let Ok(mut s) = self.state.lock() {
+            s.pinned.remove(&(height, idx));
+
+            if let Some((_, tick)) = s.entries.remove(&(height, idx)) {
+                s.order.remove(&tick);
+            }
+        }
+    }
+
+    /// Discard every node from memory
+    pub fn clear(&self) {
+        if let Ok(mut s) = self.state.lock() {
+            s.pinned.clear();
+            s.entries.clear();
+            s.order.clear();
+        }
+    }
+
+    /// Current counters of the cache
+    pub fn stats(&self) -> CacheStats {
+        let (entries, pinned) = self
+            .state
+            .lock()
+            .map(|s| (s.entries.len(), s.pinned.len()))
+            .unwrap_or((0, 0));
+
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            entries,
+            pinned,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::big_merkle_small;
+    use super::NodeCache;
+    use crate::*;
+
+    use std::convert::TryFrom;
+    use std::mem;
+
+    #[test]
+    fn node_cache_lru() {
+        let entry = mem::size_of::<u64>() + 2 * mem::size_of::<(usize, usize)>() + 16;
+        let cache = NodeCache::<u64>::new(CacheConfig {
+            memory_budget: 2 * entry,
+            pinned_levels: 1,
+            disk_interval: 2,
+            disk_persist: true,
+        });
+
+        cache.insert(0, 0, 10);
+        cache.insert(1, 0, 20);
+        cache.insert(1, 1, 21);
+        assert_eq!(Some(20), cache.get(1, 0));
+
+        // The least recently used node is evicted, but never the pinned ones
+        cache.insert(1, 2, 22);
+        assert_eq!(None, cache.get(1, 1));
+        assert_eq!(Some(20), cache.get(1, 0));
+        assert_eq!(Some(22), cache.get(1, 2));
+        assert_eq!(Some(10), cache.get(0, 0));
+
+        cache.invalidate(1, 0);
+        assert_eq!(None, cache.get(1, 0));
+
+        let stats = cache.stats();
+        assert_eq!((4, 2), (stats.hits, stats.misses));
+        assert_eq!((1, 1), (stats.entries, stats.pinned));
+    }
+
+    #[test]
+    fn big_merkle_node_cache() {
+        for mut t in big_merkle_small("big_merkle_node_cache") {
+            let mut reference = MerkleTree::<Scalar>::default();
+            for i in 0..MERKLE_WIDTH {
+                t.insert(i, Scalar::from(i as u64)).unwrap();
+                reference.insert_unchecked(i, Scalar::from(i as u64));
+            }
+
+            let root = t.root().unwrap();
+            let stats = t.cache_stats();
+            assert!(stats.misses > 0);
+            assert!(stats.pinned > 0);
+
+            assert_eq!(root, t.root().unwrap());
+            assert!(t.cache_stats().hits > stats.hits);
+
+            // Without the cache in the store, the nodes are calculated only in memory
+            let cached = |t: &BigMerkleTree<Scalar>| {
+                t.db()
+                    .iter_prefix(&[])
+                    .filter_map(|i| MerkleCoord::try_from(i.unwrap().0.as_slice()).ok())
+                    .filter(|c| c.height < t.height())
+                    .count()
+            };
+            assert!(cached(&t) > 0);
+
+            // Without the persistence, the nodes cached in the store are read but not written
+            let config = t.cache_config();
+            t.set_cache_config(CacheConfig {
+                disk_persist: false,
+                ..config
+            });
+            let cached_root = MerkleCoord::new(0, 0);
+            cached_root.persist_leaf(t.db(), Scalar::one()).unwrap();
+            assert_eq!(Scalar::one(), t.root().unwrap());
+            t.clear_cache(true).unwrap();
+            assert_eq!(root, t.root().unwrap());
+            assert_eq!(0, cached(&t));
+
+            t.set_cache_config(CacheConfig {
+                memory_budget: 0,
+                pinned_levels: 1,
+                disk_interval: 0,
+                disk_persist: true,
+            });
+            t.insert(3, Scalar::zero()).unwrap();
+            reference.insert_unchecked(3, Scalar::zero());
+            assert_eq!(reference.root(), t.root().unwrap());
+            assert_eq!(0, cached(&t));
+
+            let stats = t.cache_stats();
+            assert_eq!((0, 1), (stats.entries, stats.pinned));
+        }
+    }
+}
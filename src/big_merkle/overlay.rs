@@ -0,0 +1,337 @@
+use super::{BigMerkleTree, BigProof, Snapshot, State};
+use crate::{Error, Poseidon, PoseidonLeaf, Scalar, StoreBatch, MERKLE_ARITY};
+
+use std::collections::{BTreeMap, HashMap};
+use std::ops;
+
+/// Copy-on-write layer of leaf changes over a [`BigMerkleTree`].
+///
+/// The changes are kept in memory, and the nodes of the sub-trees without changes are read
+/// through the base tree. The base is not modified until the overlay is committed, and dropping
+/// or discarding the overlay will leave the base as it was.
+///
+/// The overlay does not lock the base, so several overlays can be built side by side and the
+/// writers of the base are not blocked. Once another mutation of the base is written, the reads
+/// and the commit of the overlay fail with [`Error::VersionMismatch`].
+#[derive(Debug)]
+pub struct TreeOverlay<'a, T: PoseidonLeaf> {
+    tree: &'a BigMerkleTree<T>,
+    /// Version of the base observed by the overlay
+    version: u64,
+    /// Locked version of the base, for the overlays over a past version
+    snapshot: Option<Snapshot<'a, T>>,
+    /// Leaves of the overlay. An absent leaf represents a removal
+    leaves: BTreeMap<usize, Option<T>>,
+    /// Calculated nodes above the changed leaves, indexed by height and index
+    nodes: HashMap<(usize, usize), Option<T>>,
+}
+
+impl<'a, T: PoseidonLeaf> TreeOverlay<'a, T>
+where
+    Scalar: ops::Mul<T, Output = T>,
+{
+    /// Create an empty overlay over the provided base
+    pub fn new(base: &'a BigMerkleTree<T>) -> Self {
+        TreeOverlay {
+            tree: base,
+            version: base.version(),
+            snapshot: None,
+            leaves: BTreeMap::new(),
+            nodes: HashMap::new(),
+        }
+    }
+
+    /// Create an empty overlay over the locked version of the base
+    pub(crate) fn from_snapshot(base: Snapshot<'a, T>) -> Self {
+        TreeOverlay {
+            tree: base.tree,
+            version: base.version(),
+            snapshot: Some(base),
+            leaves: BTreeMap::new(),
+            nodes: HashMap::new(),
+        }
+    }
+
+    /// Version of the base observed by the overlay
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// Check that the provided state of the base is the version observed by the overlay
+    fn check_version(&self, s: &State<T>) -> Result<(), Error> {
+        if s.history.version != self.version {
+            return Err(Error::VersionMismatch {
+                expected: self.version,
+                found: s.history.version,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Fetch a node of the base, if it was not modified after the version of the overlay
+    fn base_node(&self, height: usize, idx: usize) -> Result<Option<T>, Error> {
+        if let Some(snapshot) = &self.snapshot {
+            return snapshot.node(height, idx);
+        }
+
+        let s = self.tree.state();
+        self.check_version(&s)?;
+        self.tree.node_in(&s, height, idx)
+    }
+
+    /// Number of changed leaves
+    pub fn len(&self) -> usize {
+        self.leaves.len()
+    }
+
+    /// Check if the overlay has no changes
+    pub fn is_empty(&self) -> bool {
+        self.leaves.is_empty()
+    }
+
+    /// Insert the provided leaf on the provided index
+    pub fn insert(&mut self, idx: usize, leaf: T) -> Result<(), Error> {
+        self.update(idx, Some(leaf))
+    }
+
+    /// Set the provided leaf index as absent for the hash calculation
+    pub fn remove(&mut self, idx: usize) -> Result<(), Error> {
+        self.update(idx, None)
+    }
+
+    fn update(&mut self, mut idx: usize, leaf: Option<T>) -> Result<(), Error> {
+        if idx >= self.tree.width() {
+            return Err(Error::IndexOutOfBounds);
+        }
+
+        self.leaves.insert(idx, leaf);
+
+        // Invalidate the calculated ancestors of the leaf
+        for height in (0..self.tree.height()).rev() {
+            idx /= MERKLE_ARITY;
+            self.nodes.remove(&(height, idx));
+        }
+
+        Ok(())
+    }
+
+    /// Fetch a node of the tree with the changes of the overlay
+    pub fn node(&mut self, height: usize, idx: usize) -> Result<Option<T>, Error> {
+        // The calculated nodes are stale as well once the base is modified
+        if self.snapshot.is_none() {
+            self.check_version(&self.tree.state())?;
+        }
+
+        self.overlay_node(height, idx)
+    }
+
+    fn overlay_node(&mut self, height: usize, idx: usize) -> Result<Option<T>, Error> {
+        let levels = (self.tree.height() - height) as u32;
+        let from = MERKLE_ARITY.pow(levels) * idx;
+        let to = MERKLE_ARITY.pow(levels) * (idx + 1);
+
+        if self.leaves.range(from..to).next().is_none() {
+            // Sub-tree without changes
+            return self.base_node(height, idx);
+        } else if height == self.tree.height() {
+            return Ok(self.leaves.get(&idx).and_then(|l| *l));
+        } else if let Some(node) = self.nodes.get(&(height, idx)) {
+            return Ok(*node);
+        }
+
+        let mut h = Poseidon::default();
+
+        let needle = idx * MERKLE_ARITY;
+        for i in 0..MERKLE_ARITY {
+            if let Some(n) = self.overlay_node(height + 1, needle + i)? {
+                h.insert_unchecked(i, n);
+            }
+        }
+
+        let node = Some(h.hash());
+        self.nodes.insert((height, idx), node);
+
+        Ok(node)
+    }
+
+    /// Calculate and return the root of the tree with the changes of the overlay
+    pub fn root(&mut self) -> Result<T, Error> {
+        self.node(0, 0).and_then(|n| {
+            n.ok_or(Error::Other(
+                "It was not possible to obtain the root node from the merkle tree.".to_owned(),
+            ))
+        })
+    }
+
+    /// Generate a proof of membership for the provided leaf index, with the changes of the
+    /// overlay
+    pub fn proof(&mut self, mut needle: usize) -> Result<BigProof<T>, Error> {
+        let height = self.tree.height();
+        let mut proof = BigProof::with_height(height);
+        let mut leaves = [None; MERKLE_ARITY];
+
+        for row in 0..height {
+            let from = MERKLE_ARITY * (needle / MERKLE_ARITY);
+            let idx = needle % MERKLE_ARITY;
+
+            for (i, leaf) in leaves.iter_mut().enumerate() {
+                *leaf = self.node(height - row, from + i)?;
+            }
+
+            proof.push(idx, leaves);
+            needle /= MERKLE_ARITY;
+        }
+
+        Ok(proof)
+    }
+
+    /// Drop the changes of the overlay, leaving the base untouched
+    pub fn discard(self) {}
+
+    /// Atomically write the changes of the overlay into the base.
+    ///
+    /// Will fail if the base was modified after the version observed by the overlay.
+    pub fn commit(self) -> Result<(), Error> {
+        let TreeOverlay {
+            tree,
+            version,
+            snapshot,
+            leaves,
+            ..
+        } = self;
+
+        // The read lock of the snapshot cannot be upgraded, so another handle can write before
+        // the base is locked for the write
+        drop(snapshot);
+        let mut s = tree.state_mut();
+        if s.history.version != version {
+            return Err(Error::VersionMismatch {
+                expected: version,
+                found: s.history.version,
+            });
+        }
+
+        tree.update_batch_in(&mut s, StoreBatch::default(), leaves)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn tree_overlay() {
+        let store = Arc::new(MemoryStore::default());
+        let mut t = BigMerkleTree::with_store(store, MERKLE_WIDTH).unwrap();
+        let mut reference = MerkleTree::<Scalar>::default();
+
+        for i in 0..20 {
+            t.insert(i, Scalar::from(i as u64)).unwrap();
+            reference.insert_unchecked(i, Scalar::from(i as u64));
+        }
+        let root = t.root().unwrap();
+
+        let candidate = |overlay: &mut TreeOverlay<Scalar>, shift: u64| {
+            overlay.insert(3, Scalar::from(shift)).unwrap();
+            overlay.insert(40, Scalar::from(shift + 1)).unwrap();
+            overlay.remove(7).unwrap();
+            overlay.root().unwrap()
+        };
+
+        // The discarded candidate does not touch the base
+        let mut overlay = t.overlay();
+        let discarded = candidate(&mut overlay, 100);
+        assert!(overlay.insert(MERKLE_WIDTH, Scalar::one()).is_err());
+        overlay.discard();
+        assert_ne!(root, discarded);
+        assert_eq!(root, t.root().unwrap());
+
+        let mut overlay = t.overlay();
+        let committed = candidate(&mut overlay, 200);
+        reference.insert_unchecked(3, Scalar::from(200u64));
+        reference.insert_unchecked(40, Scalar::from(201u64));
+        reference.remove_unchecked(7);
+        assert_eq!(reference.root(), committed);
+
+        let proof = overlay.proof(40).unwrap();
+        assert!(proof.verify_at(40, &Scalar::from(201u64), &committed));
+        assert_eq!(&reference.proof_index(40).data()[..], &proof.data()[..]);
+
+        overlay.commit().unwrap();
+        assert_eq!(committed, t.root().unwrap());
+        assert!(t.node_is_empty(t.height(), 7));
+        assert_eq!(41, t.size());
+    }
+
+    #[test]
+    fn tree_overlay_stale() {
+        let store = Arc::new(MemoryStore::default());
+        let mut t = BigMerkleTree::with_store(store, MERKLE_WIDTH).unwrap();
+        t.insert(0, Scalar::zero()).unwrap();
+
+        // Candidates side by side, of which only the first committed is written
+        let mut first = t.overlay();
+        let mut second = t.overlay();
+        first.insert(1, Scalar::one()).unwrap();
+        second.insert(2, Scalar::one()).unwrap();
+        let root = first.root().unwrap();
+        assert_ne!(root, second.root().unwrap());
+
+        first.commit().unwrap();
+        assert_eq!(root, t.root().unwrap());
+        assert!(second.root().is_err());
+        match second.commit() {
+            Err(Error::VersionMismatch { expected, found }) => assert_eq!(expected + 1, found),
+            r => panic!("Unexpected result: {:?}", r),
+        }
+        assert!(t.node_is_empty(t.height(), 2));
+
+        // The mutations without leaf changes also make the overlays stale
+        let mut handle = t.clone();
+        let mut overlay = t.overlay();
+        overlay.insert(2, Scalar::one()).unwrap();
+        handle.checkpoint().unwrap();
+        assert!(overlay.commit().is_err());
+
+        let overlay = t.overlay();
+        handle.record_root(0).unwrap();
+        assert!(overlay.commit().is_err());
+
+        let overlay = t.overlay();
+        handle.set_leaf_index(true).unwrap();
+        assert!(overlay.commit().is_err());
+
+        let overlay = t.overlay();
+        handle.prune().unwrap();
+        assert!(overlay.commit().is_err());
+        assert_eq!(root, t.root().unwrap());
+    }
+
+    #[test]
+    fn tree_overlay_concurrent_writer() {
+        let store = Arc::new(MemoryStore::default());
+        let t = BigMerkleTree::with_store(store, MERKLE_WIDTH).unwrap();
+
+        let mut overlay = t.overlay();
+        overlay.insert(0, Scalar::one()).unwrap();
+
+        // The overlay does not block the writers of the other handles
+        let mut handle = t.clone();
+        thread::spawn(move || handle.insert(1, Scalar::one()))
+            .join()
+            .unwrap()
+            .unwrap();
+
+        assert!(overlay.root().is_err());
+        match overlay.commit() {
+            Err(Error::VersionMismatch { expected, found }) => assert_eq!(expected + 1, found),
+            r => panic!("Unexpected result: {:?}", r),
+        }
+        assert!(t.node_is_empty(t.height(), 0));
+        assert!(!t.node_is_empty(t.height(), 1));
+    }
+}
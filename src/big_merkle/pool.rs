@@ -0,0 +1,76 @@
+use crate::Error;
+
+use std::iter;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use crossbeam_deque::{Injector, Steal, Stealer, Worker};
+use crossbeam_utils::thread;
+
+/// Evaluate the provided jobs in a scoped work-stealing pool of `threads` workers.
+///
+/// Every worker takes batches of jobs from the shared queue, and steals from the other workers
+/// once the queue is drained. The first error is returned, and the jobs that were not yet started
+/// are cancelled.
+pub(crate) fn execute<J, F>(jobs: Vec<J>, threads: usize, job: F) -> Result<(), Error>
+where
+    J: Send,
+    F: Fn(J) -> Result<(), Error> + Sync,
+{
+    let threads = threads.max(1).min(jobs.len());
+    if threads == 0 {
+        return Ok(());
+    }
+
+    let injector = Injector::new();
+    jobs.into_iter().for_each(|j| injector.push(j));
+
+    let workers: Vec<Worker<J>> = (0..threads).map(|_| Worker::new_fifo()).collect();
+    let stealers: Vec<Stealer<J>> = workers.iter().map(|w| w.stealer()).collect();
+
+    let cancelled = AtomicBool::new(false);
+    let error = Mutex::new(None);
+
+    thread::scope(|s| {
+        for local in workers {
+            let (injector, stealers) = (&injector, stealers.as_slice());
+            let (cancelled, error, job) = (&cancelled, &error, &job);
+
+            s.spawn(move |_| {
+                while !cancelled.load(Ordering::Acquire) {
+                    let j = match next(&local, injector, stealers) {
+                        Some(j) => j,
+                        None => break,
+                    };
+
+                    if let Err(e) = job(j) {
+                        cancelled.store(true, Ordering::Release);
+                        if let Ok(mut error) = error.lock() {
+                            error.get_or_insert(e);
+                        }
+                    }
+                }
+            });
+        }
+    })
+    .map_err(|_| Error::Other("A worker of the pool panicked.".to_owned()))?;
+
+    match error.into_inner() {
+        Ok(Some(e)) => Err(e),
+        Ok(None) => Ok(()),
+        Err(e) => Err(Error::Other(e.to_string())),
+    }
+}
+
+/// Pop a job from the local queue or, if empty, steal one from the shared queue or another worker
+fn next<J>(local: &Worker<J>, injector: &Injector<J>, stealers: &[Stealer<J>]) -> Option<J> {
+    local.pop().or_else(|| {
+        iter::repeat_with(|| {
+            injector
+                .steal_batch_and_pop(local)
+                .or_else(|| stealers.iter().map(|s| s.steal()).collect::<Steal<J>>())
+        })
+        .find(|s| !s.is_retry())
+        .and_then(|s| s.success())
+    })
+}
@@ -1,4 +1,6 @@
-use crate::{Poseidon, PoseidonLeaf, Scalar, MERKLE_ARITY};
+use crate::encoding::{decode_levels, encode_levels};
+use crate::proof::{path_index, verify_levels};
+use crate::{Error, PoseidonLeaf, Scalar, VerifyError, MERKLE_ARITY, MERKLE_HEIGHT};
 
 use std::ops;
 
@@ -13,15 +15,31 @@ use std::ops;
 ///
 /// The leaves will define the other elements required to perform the hash for that level of the
 /// tree.
+///
+/// The proof holds the height of its tree, and is only valid if it has one level for every level
+/// of the tree. The height is never taken from an untrusted source: it is either the height of
+/// the tree that generated the proof, or the height of the verifier provided to
+/// [`BigProof::from_bytes`].
 #[derive(Debug, Clone, PartialEq)]
 pub struct BigProof<T: PoseidonLeaf> {
+    height: usize,
     data: Vec<(usize, [Option<T>; MERKLE_ARITY])>,
 }
 
 impl<T: PoseidonLeaf> BigProof<T> {
-    /// BigProof constructor
+    /// BigProof constructor, for a tree with the default height, [`MERKLE_HEIGHT`]
+    ///
+    /// [`MERKLE_HEIGHT`]: crate::MERKLE_HEIGHT
     pub fn new() -> Self {
-        BigProof { data: vec![] }
+        BigProof::with_height(MERKLE_HEIGHT)
+    }
+
+    /// BigProof constructor, for a tree with the provided height
+    pub(crate) fn with_height(height: usize) -> Self {
+        BigProof {
+            height,
+            data: vec![],
+        }
     }
 
     pub(crate) fn push(&mut self, idx: usize, leaves: [Option<T>; MERKLE_ARITY]) {
@@ -33,55 +51,232 @@ impl<T: PoseidonLeaf> BigProof<T> {
         &self.data
     }
 
+    /// Height of the tree of the proof
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Reconstruct the absolute index of the proven leaf from the position of every level
+    pub fn index(&self) -> usize {
+        path_index(self.data.iter().map(|(idx, _)| *idx))
+    }
+
+    /// Verify if the provided leaf corresponds to the proof in the merkle construction, and if
+    /// the path of the proof leads to the provided leaf index.
+    pub fn verify_at(&self, index: usize, leaf: &T, root: &T) -> bool
+    where
+        Scalar: ops::Mul<T, Output = T>,
+    {
+        self.verify_at_detailed(index, leaf, root).is_ok()
+    }
+
     /// Verify if the provided leaf corresponds to the proof in the merkle construction
     pub fn verify(&self, leaf: &T, root: &T) -> bool
     where
         Scalar: ops::Mul<T, Output = T>,
     {
-        let mut leaf = *leaf;
-        let mut h = Poseidon::default();
+        self.verify_detailed(leaf, root).is_ok()
+    }
 
-        self.data.iter().for_each(|(idx, data)| {
-            h.replace(&data[0..MERKLE_ARITY]);
-            h.insert_unchecked(*idx, leaf);
+    /// Same as [`BigProof::verify_at`], but reporting the reason of a failure
+    pub fn verify_at_detailed(&self, index: usize, leaf: &T, root: &T) -> Result<(), VerifyError>
+    where
+        Scalar: ops::Mul<T, Output = T>,
+    {
+        verify_levels(&self.data, self.height, Some(index), leaf, root)
+    }
+
+    /// Same as [`BigProof::verify`], but reporting the reason of a failure
+    pub fn verify_detailed(&self, leaf: &T, root: &T) -> Result<(), VerifyError>
+    where
+        Scalar: ops::Mul<T, Output = T>,
+    {
+        verify_levels(&self.data, self.height, None, leaf, root)
+    }
+
+    /// Check if the proof belongs to a tree with the provided height, and has one level for
+    /// every level of it.
+    ///
+    /// The verification of the proof checks the levels against the height of the proof, which is
+    /// already bound to the height of the verifier by [`BigProof::from_bytes`].
+    pub fn verify_height(&self, height: usize) -> Result<(), VerifyError> {
+        let found = if self.height != height {
+            self.height
+        } else {
+            self.data.len()
+        };
+
+        if found != height {
+            return Err(VerifyError::WrongHeight {
+                expected: height,
+                found,
+            });
+        }
+
+        Ok(())
+    }
+}
 
-            leaf = h.hash();
-        });
+impl BigProof<Scalar> {
+    /// Serialize the proof into its canonical wire format.
+    ///
+    /// For every level, the position index is followed by a bitmap of the present leaves, and
+    /// only the present leaves are encoded.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        encode_levels(self.height, &self.data)
+    }
 
-        &leaf == root
+    /// Deserialize a proof of a tree with the provided height from its canonical wire format.
+    ///
+    /// Will fail if the encoded height or the number of levels is not the provided height, or if
+    /// the provided bytes are not a canonical encoding.
+    pub fn from_bytes(bytes: &[u8], height: usize) -> Result<Self, Error> {
+        decode_levels(bytes, height).map(|data| BigProof { height, data })
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::super::big_merkle_default;
+    use super::super::big_merkle_stores;
     use crate::*;
 
     #[test]
     fn big_proof_verify() {
-        let mut t = big_merkle_default("big_proof_verify");
-        for i in 0..64 {
-            t.insert(i, Scalar::from(i as u64)).unwrap();
-        }
+        for mut t in big_merkle_stores("big_proof_verify") {
+            for i in 0..64 {
+                t.insert(i, Scalar::from(i as u64)).unwrap();
+            }
 
-        let root = t.root().unwrap();
-        let i = 21;
+            let root = t.root().unwrap();
+            let i = 21;
 
-        let proof = t.proof(i).unwrap();
-        assert!(proof.verify(&Scalar::from(i as u64), &root));
+            let proof = t.proof(i).unwrap();
+            assert!(proof.verify(&Scalar::from(i as u64), &root));
+        }
     }
 
     #[test]
     fn big_proof_verify_failure() {
-        let mut t = big_merkle_default("big_proof_verify_failure");
-        for i in 0..64 {
-            t.insert(i, Scalar::from(i as u64)).unwrap();
+        for mut t in big_merkle_stores("big_proof_verify_failure") {
+            for i in 0..64 {
+                t.insert(i, Scalar::from(i as u64)).unwrap();
+            }
+
+            let root = t.root().unwrap();
+            let i = 21;
+
+            let proof = t.proof(i + 1).unwrap();
+            assert!(!proof.verify(&Scalar::from(i as u64), &root));
         }
+    }
+
+    #[test]
+    fn big_proof_verify_at() {
+        for mut t in big_merkle_stores("big_proof_verify_at") {
+            for i in 0..64 {
+                t.insert(i, Scalar::from((i % 2) as u64)).unwrap();
+            }
+
+            let root = t.root().unwrap();
+            let i = 21;
+
+            let proof = t.proof(i).unwrap();
+            assert_eq!(i, proof.index());
+
+            let leaf = Scalar::from((i % 2) as u64);
+            assert!(proof.verify_at(i, &leaf, &root));
+            assert!(!proof.verify_at(i + 2, &leaf, &root));
+            assert!(!proof.verify_at(i + t.width(), &leaf, &root));
+        }
+    }
+
+    #[test]
+    fn big_proof_verify_detailed() {
+        for mut t in big_merkle_stores("big_proof_verify_detailed") {
+            for i in 0..64 {
+                t.insert(i, Scalar::from(i as u64)).unwrap();
+            }
 
-        let root = t.root().unwrap();
-        let i = 21;
+            let root = t.root().unwrap();
+            let i = 21;
+            let leaf = Scalar::from(i as u64);
 
-        let proof = t.proof(i + 1).unwrap();
-        assert!(!proof.verify(&Scalar::from(i as u64), &root));
+            let proof = t.proof(i).unwrap();
+            assert_eq!(Ok(()), proof.verify_height(t.height()));
+            assert_eq!(Ok(()), proof.verify_at_detailed(i, &leaf, &root));
+            assert_eq!(
+                Err(VerifyError::WrongLeaf),
+                proof.verify_detailed(&Scalar::zero(), &root)
+            );
+            assert!(proof.verify_height(t.height() + 1).is_err());
+
+            // A truncated proof cannot prove an inner node as a leaf
+            let mut short = BigProof::with_height(t.height());
+            short.data = proof.data[1..].to_vec();
+            let node = t.node(t.height() - 1, i / MERKLE_ARITY).unwrap().unwrap();
+            assert_eq!(
+                Err(VerifyError::WrongHeight {
+                    expected: t.height(),
+                    found: t.height() - 1
+                }),
+                short.verify_detailed(&node, &root)
+            );
+            assert!(!BigProof::new().verify_at(0, &root, &root));
+
+            // The encoded height of a truncated proof is lowered to match its levels, so the
+            // height of the verifier is the only one trusted
+            let mut forged = BigProof::with_height(t.height() - 1);
+            forged.data = proof.data[1..].to_vec();
+            assert!(forged.verify_at(i / MERKLE_ARITY, &node, &root));
+            let bytes = forged.to_bytes();
+            assert_eq!(t.height() - 1, bytes[1] as usize);
+            assert!(BigProof::from_bytes(bytes.as_slice(), t.height()).is_err());
+
+            let mut tampered = proof.clone();
+            let level = 2;
+            let sibling = (tampered.data[level].0 + 1) % MERKLE_ARITY;
+            tampered.data[level].1[sibling] = Some(Scalar::zero());
+            assert_eq!(
+                Err(VerifyError::BadSibling { level }),
+                tampered.verify_detailed(&leaf, &root)
+            );
+        }
+    }
+
+    #[test]
+    fn big_proof_bytes() {
+        for mut t in big_merkle_stores("big_proof_bytes") {
+            for i in 0..64 {
+                t.insert(i, Scalar::from(i as u64)).unwrap();
+            }
+
+            let root = t.root().unwrap();
+            let i = 21;
+
+            let proof = t.proof(i).unwrap();
+            let bytes = proof.to_bytes();
+            let decoded = BigProof::from_bytes(bytes.as_slice(), t.height()).unwrap();
+
+            assert_eq!(proof, decoded);
+            assert!(decoded.verify(&Scalar::from(i as u64), &root));
+            assert!(BigProof::from_bytes(&bytes[..bytes.len() - 1], t.height()).is_err());
+
+            // The encoded height and levels are checked against the height of the verifier
+            assert!(BigProof::<Scalar>::from_bytes(bytes.as_slice(), t.height() + 1).is_err());
+            let mut short = BigProof::with_height(t.height());
+            short.data = proof.data[1..].to_vec();
+            assert!(BigProof::from_bytes(short.to_bytes().as_slice(), t.height()).is_err());
+        }
+
+        // The number of levels is not bound to a single byte
+        let mut deep = BigProof::with_height(300);
+        for i in 0..300 {
+            deep.push(
+                i % MERKLE_ARITY,
+                [Some(Scalar::from(i as u64)); MERKLE_ARITY],
+            );
+        }
+        let decoded = BigProof::from_bytes(deep.to_bytes().as_slice(), 300).unwrap();
+        assert_eq!(deep, decoded);
     }
 }
@@ -0,0 +1,119 @@
+use super::IntervalSet;
+use crate::store::fetch_raw;
+use crate::{Error, MerkleStore, StoreBatch};
+
+use std::collections::BTreeSet;
+
+use serde::{Deserialize, Serialize};
+
+/// Key of the persisted pruning state. It cannot collide with the serialized coordinates.
+const PRUNING_KEY: &[u8] = b"pruning";
+
+/// Prefix of the pinned nodes, followed by the big-endian height and index
+const PIN_PREFIX: &[u8] = b"pn";
+
+/// Leaves retained by a pruned tree, and the ranges of the base that were discarded
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub(crate) struct Pruning {
+    /// Leaves that will never be pruned
+    pub marked: BTreeSet<usize>,
+    /// Ranges of the base that are represented only by their pinned sub-tree nodes
+    pub pruned: IntervalSet,
+}
+
+impl Pruning {
+    /// Fetch the pruning state persisted in the store, if any
+    pub fn fetch(db: &dyn MerkleStore) -> Result<Self, Error> {
+        fetch_raw(db, PRUNING_KEY).map(|p| p.unwrap_or_default())
+    }
+
+    /// Append the persistence of the pruning state to the provided batch
+    pub fn batch(&self, batch: &mut StoreBatch) -> Result<(), Error> {
+        let pruning = bincode::serialize(self).map_err(|e| Error::Other(e.to_string()))?;
+        batch.put(PRUNING_KEY, pruning);
+
+        Ok(())
+    }
+
+    /// Key of the pinned node of a pruned sub-tree
+    pub fn pin_key(height: usize, idx: usize) -> Vec<u8> {
+        let mut key = PIN_PREFIX.to_vec();
+        key.extend_from_slice(&(height as u64).to_be_bytes());
+        key.extend_from_slice(&(idx as u64).to_be_bytes());
+        key
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::big_merkle_small;
+    use crate::*;
+
+    use std::sync::Arc;
+
+    #[test]
+    fn big_merkle_prune() {
+        for mut t in big_merkle_small("big_merkle_prune") {
+            let mut reference = MerkleTree::<Scalar>::default();
+
+            for i in 0..40 {
+                t.insert(i, Scalar::from(i as u64)).unwrap();
+                reference.insert_unchecked(i, Scalar::from(i as u64));
+            }
+            t.remove(30).unwrap();
+            reference.remove_unchecked(30);
+
+            t.mark(5).unwrap();
+            t.mark(22).unwrap();
+            t.set_history_horizon(Some(10)).unwrap();
+            t.insert(10, Scalar::from(10u64)).unwrap();
+            let version = t.version();
+            let entries = t.db().iter_prefix(&[]).count();
+
+            t.prune().unwrap();
+            assert!(t.db().iter_prefix(&[]).count() < entries);
+
+            // The versions before the pruning are no longer retained
+            assert_eq!(Some(t.version()), t.oldest_version());
+            match t.proof_at(version, 5) {
+                Err(Error::VersionNotRetained(v)) => assert_eq!(version, v),
+                _ => panic!("A version before the pruning was restored"),
+            }
+            assert!(t.root_at(version - 1).is_err());
+            assert_eq!(reference.root(), t.root_at(t.version()).unwrap());
+
+            assert!(t.is_pruned(t.height(), 10));
+            assert!(!t.is_pruned(t.height(), 5));
+            assert_eq!(reference.root(), t.root().unwrap());
+
+            for i in vec![5, 22] {
+                let proof = t.proof(i).unwrap();
+                assert_eq!(&reference.proof_index(i).data()[..], &proof.data()[..]);
+                assert!(proof.verify_at(i, &Scalar::from(i as u64), &reference.root()));
+            }
+
+            // Only the marked leaves and the appends can be changed
+            match t.insert(10, Scalar::one()) {
+                Err(Error::LeafPruned) => (),
+                _ => panic!("A pruned leaf was changed"),
+            }
+            assert!(t.proof(10).is_err());
+
+            t.insert(5, Scalar::one()).unwrap();
+            t.insert(40, Scalar::one()).unwrap();
+            reference.insert_unchecked(5, Scalar::one());
+            reference.insert_unchecked(40, Scalar::one());
+            assert_eq!(reference.root(), t.root().unwrap());
+
+            // The pruning is persisted, and can be extended
+            let mut t = BigMerkleTree::<Scalar>::open_store(Arc::clone(&t.db)).unwrap();
+            t.unmark(22).unwrap();
+            t.prune().unwrap();
+            assert!(t.is_pruned(t.height(), 22));
+            assert_eq!(reference.root(), t.root().unwrap());
+
+            let proof = t.proof(5).unwrap();
+            assert!(proof.verify_at(5, &Scalar::one(), &reference.root()));
+        }
+    }
+}
@@ -0,0 +1,104 @@
+use super::{BigMerkleTree, BigProof, State};
+use crate::{Error, PoseidonLeaf, Scalar};
+
+use std::ops;
+use std::sync::RwLockReadGuard;
+
+/// Consistent view of the current version of a [`BigMerkleTree`].
+///
+/// The snapshot holds the read lock of the tree, so every read of the snapshot observes the same
+/// version, and the mutations of every handle of the tree wait until it is dropped. The tree
+/// should not be read through other means while the snapshot is alive, and the thread holding
+/// the snapshot must not mutate the tree through any handle, as the mutation would never acquire
+/// the lock.
+///
+/// The reads may persist the calculated nodes in the cache of the store. These writes are
+/// best-effort: a failure to persist a node does not fail the read.
+#[derive(Debug)]
+pub struct Snapshot<'a, T: PoseidonLeaf> {
+    pub(super) tree: &'a BigMerkleTree<T>,
+    pub(super) state: RwLockReadGuard<'a, State<T>>,
+}
+
+impl<'a, T: PoseidonLeaf> Snapshot<'a, T>
+where
+    Scalar: ops::Mul<T, Output = T>,
+{
+    /// Lock the current version of the provided tree
+    pub(super) fn new(tree: &'a BigMerkleTree<T>) -> Self {
+        Snapshot {
+            tree,
+            state: tree.state(),
+        }
+    }
+
+    /// Version of the tree observed by the snapshot
+    pub fn version(&self) -> u64 {
+        self.state.history.version
+    }
+
+    /// Number of leaves up to the last present one
+    pub fn size(&self) -> usize {
+        self.tree.size_in(&self.state)
+    }
+
+    /// Fetch a node of the tree for the provided coordinates
+    pub fn node(&self, height: usize, idx: usize) -> Result<Option<T>, Error> {
+        self.tree.node_in(&self.state, height, idx)
+    }
+
+    /// Generate a proof of membership for the provided leaf index
+    pub fn proof(&self, idx: usize) -> Result<BigProof<T>, Error> {
+        self.tree.proof_in(&self.state, idx)
+    }
+
+    /// Calculate and return the root of the merkle tree
+    pub fn root(&self) -> Result<T, Error> {
+        self.tree.root_in(&self.state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::big_merkle_small;
+    use crate::*;
+
+    use std::thread;
+
+    #[test]
+    fn big_merkle_concurrent_readers() {
+        for mut t in big_merkle_small("big_merkle_concurrent_readers") {
+            t.insert(0, Scalar::zero()).unwrap();
+
+            // The clones share the state, so they observe the writes of the other handles
+            let reader = t.clone();
+            t.insert(1, Scalar::one()).unwrap();
+            assert_eq!(2, reader.size());
+            assert_eq!(t.root().unwrap(), reader.root().unwrap());
+
+            let readers: Vec<_> = (0..4)
+                .map(|_| {
+                    let reader = t.clone();
+
+                    thread::spawn(move || {
+                        for _ in 0..20 {
+                            let snapshot = reader.snapshot();
+                            let root = snapshot.root().unwrap();
+                            let idx = snapshot.size() - 1;
+
+                            let proof = snapshot.proof(idx).unwrap();
+                            assert!(proof.verify_at(idx, &Scalar::from(idx as u64), &root));
+                        }
+                    })
+                })
+                .collect();
+
+            for i in 2..MERKLE_WIDTH {
+                t.insert(i, Scalar::from(i as u64)).unwrap();
+            }
+
+            readers.into_iter().for_each(|r| r.join().unwrap());
+            assert_eq!(MERKLE_WIDTH, reader.size());
+        }
+    }
+}
@@ -0,0 +1,169 @@
+//! Canonical wire format for the merkle proofs.
+//!
+//! ```text
+//! version: u8
+//! height: varint, height of the tree of the proof
+//! levels: varint
+//! for every level:
+//!     idx: varint
+//!     bitmap: [u8; (MERKLE_ARITY + 7) / 8], little-endian bit set of the present leaves
+//!     leaves: [[u8; 32]; present leaves], canonical scalars
+//! ```
+//!
+//! The varints are unsigned LEB128 integers, in their shortest form. The values below `128` are a
+//! single byte.
+
+use crate::{Error, Scalar, MERKLE_ARITY};
+
+use std::cmp;
+use std::mem;
+
+/// Current version of the proof wire format
+pub(crate) const PROOF_VERSION: u8 = 1;
+
+const BITMAP_LEN: usize = (MERKLE_ARITY + 7) / 8;
+
+/// Position index and leaves of a single level of a proof
+pub(crate) type ProofLevel = (usize, [Option<Scalar>; MERKLE_ARITY]);
+
+/// Encode the height of a tree and a set of proof levels into the canonical wire format
+pub(crate) fn encode_levels(height: usize, levels: &[ProofLevel]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(3 + levels.len() * (1 + BITMAP_LEN + 32 * MERKLE_ARITY));
+
+    bytes.push(PROOF_VERSION);
+    push_varint(&mut bytes, height);
+    push_varint(&mut bytes, levels.len());
+
+    for (idx, leaves) in levels {
+        let mut bitmap = [0u8; BITMAP_LEN];
+        leaves
+            .iter()
+            .enumerate()
+            .filter(|(_, l)| l.is_some())
+            .for_each(|(i, _)| bitmap[i / 8] |= 1 << (i % 8));
+
+        push_varint(&mut bytes, *idx);
+        bytes.extend_from_slice(&bitmap);
+        leaves
+            .iter()
+            .filter_map(|l| l.as_ref())
+            .for_each(|l| bytes.extend_from_slice(l.as_bytes()));
+    }
+
+    bytes
+}
+
+/// Decode a set of proof levels of a tree with the provided height from the canonical wire
+/// format.
+///
+/// Will reject unknown versions, encoded heights or numbers of levels that are not the provided
+/// height, positions out of the arity, non-canonical scalars and buffers that are not entirely
+/// consumed.
+pub(crate) fn decode_levels(bytes: &[u8], height: usize) -> Result<Vec<ProofLevel>, Error> {
+    let mut reader = Reader(bytes);
+
+    let version = reader.take(1)?[0];
+    if version != PROOF_VERSION {
+        return Err(Error::UnsupportedVersion(version));
+    }
+
+    // The encoded height is never trusted, since it is provided by the sender
+    if reader.varint()? != height {
+        return Err(Error::InvalidLength);
+    }
+
+    // Every level takes at least its position and bitmap, so the capacity is bound by the buffer
+    let len = reader.varint()?;
+    if len != height {
+        return Err(Error::InvalidLength);
+    }
+    let mut levels = Vec::with_capacity(cmp::min(len, reader.0.len() / (1 + BITMAP_LEN)));
+
+    for _ in 0..len {
+        let idx = reader.varint()?;
+        if idx >= MERKLE_ARITY {
+            return Err(Error::IndexOutOfBounds);
+        }
+
+        let bitmap = reader.take(BITMAP_LEN)?;
+        let mut leaves = [None; MERKLE_ARITY];
+
+        for i in 0..BITMAP_LEN * 8 {
+            if bitmap[i / 8] & (1 << (i % 8)) == 0 {
+                continue;
+            }
+
+            // The bits beyond the arity must not be set
+            if i >= MERKLE_ARITY {
+                return Err(Error::IndexOutOfBounds);
+            }
+
+            let mut scalar = [0u8; 32];
+            scalar.copy_from_slice(reader.take(32)?);
+
+            leaves[i] =
+                Some(Scalar::from_canonical_bytes(scalar).ok_or(Error::NonCanonicalScalar)?);
+        }
+
+        levels.push((idx, leaves));
+    }
+
+    if !reader.0.is_empty() {
+        return Err(Error::InvalidLength);
+    }
+
+    Ok(levels)
+}
+
+/// Append the provided integer as an unsigned LEB128 varint
+fn push_varint(bytes: &mut Vec<u8>, mut n: usize) {
+    while n >= 0x80 {
+        bytes.push(n as u8 | 0x80);
+        n >>= 7;
+    }
+
+    bytes.push(n as u8);
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], Error> {
+        if self.0.len() < n {
+            return Err(Error::InvalidLength);
+        }
+
+        let (bytes, remainder) = self.0.split_at(n);
+        self.0 = remainder;
+
+        Ok(bytes)
+    }
+
+    /// Read an unsigned LEB128 varint, rejecting the ones that are not in their shortest form or
+    /// that overflow a `usize`
+    fn varint(&mut self) -> Result<usize, Error> {
+        let mut n: usize = 0;
+        let mut shift = 0;
+
+        loop {
+            let b = self.take(1)?[0];
+            let bits = (b & 0x7f) as usize;
+
+            if shift >= mem::size_of::<usize>() * 8 || (bits << shift) >> shift != bits {
+                return Err(Error::InvalidLength);
+            }
+            n |= bits << shift;
+
+            if b & 0x80 == 0 {
+                // A trailing zero byte would be a longer form of the same integer
+                if b == 0 && shift > 0 {
+                    return Err(Error::InvalidLength);
+                }
+
+                return Ok(n);
+            }
+
+            shift += 7;
+        }
+    }
+}
@@ -9,6 +9,44 @@ pub enum Error {
     IndexOutOfBounds,
     /// The provided leaf was not found in the tree
     LeafNotFound,
+    /// The provided leaf is already present in the tree
+    LeafAlreadyExists,
+    /// The provided bytes do not represent a canonical scalar
+    NonCanonicalScalar,
+    /// The provided bytes have an invalid length for the encoded structure
+    InvalidLength,
+    /// The encoded structure was created with an unsupported version
+    UnsupportedVersion(u8),
+    /// The persisted tree was created with a different parameter
+    MetadataMismatch {
+        /// Name of the parameter
+        parameter: &'static str,
+        /// Value of the parameter that was requested
+        expected: usize,
+        /// Value of the parameter persisted with the tree
+        found: usize,
+    },
+    /// There is no empty index left in the merkle tree
+    FullTree,
+    /// The leaves of the tree are not indexed by value
+    LeafIndexDisabled,
+    /// The width of the tree is not a power of its arity
+    InvalidWidth(usize),
+    /// The provided leaf was discarded by the pruning of the tree
+    LeafPruned,
+    /// The provided checkpoint is not retained by the tree
+    CheckpointNotFound(u64),
+    /// The provided version is not retained by the history of the tree
+    VersionNotRetained(u64),
+    /// The tree was modified after the version an overlay was created on
+    VersionMismatch {
+        /// Version of the tree observed by the overlay
+        expected: u64,
+        /// Current version of the tree
+        found: u64,
+    },
+    /// The tree shares its store with records that cannot be restored by a rollback
+    RollbackUnsupported,
     /// Other errors
     Other(String),
 }
@@ -24,7 +62,128 @@ impl fmt::Display for Error {
             ),
             Error::IndexOutOfBounds => write!(f, "The referenced index is outs of bounds."),
             Error::LeafNotFound => write!(f, "The provided leaf is not present in the tree."),
+            Error::LeafAlreadyExists => {
+                write!(f, "The provided leaf is already present in the tree.")
+            }
+            Error::NonCanonicalScalar => {
+                write!(f, "The provided bytes are not a canonical scalar.")
+            }
+            Error::InvalidLength => write!(f, "The provided bytes have an invalid length."),
+            Error::UnsupportedVersion(v) => write!(f, "The version {} is not supported.", v),
+            Error::MetadataMismatch {
+                parameter,
+                expected,
+                found,
+            } => write!(
+                f,
+                "The persisted tree has the {} {}, but {} was expected.",
+                parameter, found, expected
+            ),
+            Error::FullTree => write!(f, "The merkle tree has no empty index left."),
+            Error::LeafIndexDisabled => {
+                write!(f, "The leaves of the merkle tree are not indexed by value.")
+            }
+            Error::InvalidWidth(w) => write!(
+                f,
+                "The width {} is not a power of the arity of the merkle tree.",
+                w
+            ),
+            Error::LeafPruned => write!(f, "The provided leaf was pruned from the tree."),
+            Error::CheckpointNotFound(id) => {
+                write!(f, "The checkpoint {} is not retained by the tree.", id)
+            }
+            Error::VersionNotRetained(v) => {
+                write!(f, "The version {} is not retained by the tree.", v)
+            }
+            Error::VersionMismatch { expected, found } => write!(
+                f,
+                "The tree has the version {}, but {} was expected.",
+                found, expected
+            ),
+            Error::RollbackUnsupported => write!(
+                f,
+                "The tree holds records that cannot be restored by a rollback."
+            ),
             Error::Other(s) => write!(f, "{}", s),
         }
     }
 }
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Possible reasons for a proof verification to fail.
+pub enum VerifyError {
+    /// The number of levels of the proof is not the height of the tree
+    WrongHeight {
+        /// Height of the tree
+        expected: usize,
+        /// Number of levels of the proof
+        found: usize,
+    },
+    /// The position of the path in the provided level is not smaller than the arity of the tree
+    IndexOutOfRange {
+        /// Level of the proof, starting from the base of the tree
+        level: usize,
+        /// Position of the path in the level
+        idx: usize,
+    },
+    /// The path of the proof does not lead to the expected leaf index
+    IndexMismatch {
+        /// Expected leaf index
+        expected: usize,
+        /// Leaf index reconstructed from the path
+        found: usize,
+    },
+    /// The slot of the path in the base of the tree is occupied by a different leaf
+    WrongLeaf,
+    /// The leaves of the provided level do not hash into the node that occupies the slot of the
+    /// path in the level above
+    BadSibling {
+        /// Level of the proof, starting from the base of the tree
+        level: usize,
+    },
+    /// The reconstructed root is not the provided one
+    RootMismatch,
+    /// The old size of a consistency proof is greater than the new one
+    SizeMismatch {
+        /// Size of the old tree
+        old: usize,
+        /// Size of the new tree
+        new: usize,
+    },
+}
+
+impl error::Error for VerifyError {}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            VerifyError::WrongHeight { expected, found } => write!(
+                f,
+                "The proof has {} levels, but the tree has a height of {}.",
+                found, expected
+            ),
+            VerifyError::IndexOutOfRange { level, idx } => write!(
+                f,
+                "The position {} of the level {} is out of the arity of the tree.",
+                idx, level
+            ),
+            VerifyError::IndexMismatch { expected, found } => write!(
+                f,
+                "The proof leads to the index {}, but the index {} was expected.",
+                found, expected
+            ),
+            VerifyError::WrongLeaf => write!(f, "The proof slot is occupied by a different leaf."),
+            VerifyError::BadSibling { level } => write!(
+                f,
+                "The leaves of the level {} do not hash into the path of the proof.",
+                level
+            ),
+            VerifyError::RootMismatch => write!(f, "The reconstructed root does not match."),
+            VerifyError::SizeMismatch { old, new } => write!(
+                f,
+                "The old size {} is greater than the new size {}.",
+                old, new
+            ),
+        }
+    }
+}
@@ -0,0 +1,351 @@
+use crate::{Error, Poseidon, Scalar};
+
+use std::cmp::Ordering;
+
+#[cfg(feature = "big-merkle")]
+use serde::{Deserialize, Serialize};
+
+pub use proof::{IndexedLeafProof, IndexedProof};
+#[cfg(feature = "big-merkle")]
+pub use storage::BigIndexedStorage;
+pub use storage::{IndexedStorage, MemoryIndexedStorage};
+
+mod proof;
+mod storage;
+
+/// Leaf of an [`IndexedMerkleTree`].
+///
+/// The leaves form a sorted linked list. Every leaf points to the leaf with the next greater
+/// value, and the greatest value of the tree points to the index `0` with the value `0`.
+#[cfg_attr(feature = "big-merkle", derive(Serialize, Deserialize))]
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct IndexedLeaf {
+    /// Value stored in the leaf
+    pub value: Scalar,
+    /// Index of the leaf with the next greater value
+    pub next_index: usize,
+    /// Next greater value of the tree
+    pub next_value: Scalar,
+}
+
+impl Default for IndexedLeaf {
+    fn default() -> Self {
+        IndexedLeaf {
+            value: Scalar::zero(),
+            next_index: 0,
+            next_value: Scalar::zero(),
+        }
+    }
+}
+
+impl IndexedLeaf {
+    /// IndexedLeaf constructor
+    pub fn new(value: Scalar, next_index: usize, next_value: Scalar) -> Self {
+        IndexedLeaf {
+            value,
+            next_index,
+            next_value,
+        }
+    }
+
+    /// Check if this is the leaf with the greatest value of the tree
+    pub fn is_last(&self) -> bool {
+        self.next_index == 0 && self.next_value == Scalar::zero()
+    }
+
+    /// Check if the provided value falls strictly between this leaf and the next one.
+    ///
+    /// If this is true, then the provided value is not a member of the tree.
+    pub fn is_low_leaf_of(&self, value: &Scalar) -> bool {
+        cmp_scalar(&self.value, value) == Ordering::Less
+            && (self.is_last() || cmp_scalar(value, &self.next_value) == Ordering::Less)
+    }
+
+    /// Poseidon hash of `(value, next_index, next_value)`.
+    ///
+    /// If the arity of the tree is smaller than the number of elements, the digest of the
+    /// previous elements is chained as the first input of the next permutation.
+    pub fn hash(&self) -> Scalar {
+        let inputs = [
+            self.value,
+            Scalar::from(self.next_index as u64),
+            self.next_value,
+        ];
+        let mut h = Poseidon::default();
+
+        for i in inputs.iter() {
+            if h.push(*i).is_err() {
+                let digest = h.hash();
+
+                // The arity is at least 2, so both elements fit in the restarted buffer
+                h.reset();
+                h.push(digest).ok();
+                h.push(*i).ok();
+            }
+        }
+
+        h.hash()
+    }
+}
+
+/// Compare two scalars by their canonical integer representation.
+pub(crate) fn cmp_scalar(a: &Scalar, b: &Scalar) -> Ordering {
+    a.as_bytes().iter().rev().cmp(b.as_bytes().iter().rev())
+}
+
+/// Sorted merkle tree that supports proofs of membership and non-membership.
+///
+/// The index `0` is reserved for a sentinel leaf with the value `0`. Therefore, `0` is always a
+/// member of the tree.
+///
+/// Every new value is appended to the next free index of the storage, and the leaf with the
+/// greatest value smaller than the inserted one (the low leaf) is updated to point to it.
+pub struct IndexedMerkleTree<S: IndexedStorage> {
+    storage: S,
+}
+
+impl<S: IndexedStorage> IndexedMerkleTree<S> {
+    /// IndexedMerkleTree constructor.
+    ///
+    /// Will insert the sentinel leaf if the provided storage is empty.
+    pub fn new(mut storage: S) -> Result<Self, Error> {
+        if storage.is_empty() {
+            storage.put_leaves(&[(0, IndexedLeaf::default())])?;
+        }
+
+        Ok(IndexedMerkleTree { storage })
+    }
+
+    /// Return a reference to the underlying storage
+    pub fn storage(&self) -> &S {
+        &self.storage
+    }
+
+    /// Number of leaves in the tree, including the sentinel
+    pub fn len(&self) -> usize {
+        self.storage.len()
+    }
+
+    /// Check if the tree contains only the sentinel leaf
+    pub fn is_empty(&self) -> bool {
+        self.storage.len() <= 1
+    }
+
+    /// Fetch the leaf stored in the provided index
+    pub fn leaf(&self, idx: usize) -> Result<Option<IndexedLeaf>, Error> {
+        self.storage.leaf(idx)
+    }
+
+    /// Check if the provided value is a member of the tree
+    pub fn contains(&self, value: &Scalar) -> Result<bool, Error> {
+        self.storage
+            .low_leaf(value)
+            .map(|(_, leaf)| &leaf.value == value)
+    }
+
+    /// Insert the provided value in the tree, returning the index of the new leaf.
+    pub fn insert(&mut self, value: Scalar) -> Result<usize, Error> {
+        let (low_idx, mut low) = self.storage.low_leaf(&value)?;
+        if low.value == value {
+            return Err(Error::LeafAlreadyExists);
+        }
+
+        let idx = self.storage.len();
+        if idx >= self.storage.capacity() {
+            return Err(Error::FullBuffer);
+        }
+
+        let leaf = IndexedLeaf::new(value, low.next_index, low.next_value);
+        low.next_index = idx;
+        low.next_value = value;
+
+        // The new leaf and the updated low leaf are persisted atomically
+        self.storage.put_leaves(&[(idx, leaf), (low_idx, low)])?;
+
+        Ok(idx)
+    }
+
+    /// Calculate and return the root of the merkle tree.
+    pub fn root(&mut self) -> Result<Scalar, Error> {
+        self.storage.root()
+    }
+
+    /// Generate a proof of membership for the provided value
+    pub fn membership_proof(&mut self, value: &Scalar) -> Result<IndexedProof<S::Proof>, Error> {
+        let (idx, leaf) = self.storage.low_leaf(value)?;
+        if &leaf.value != value {
+            return Err(Error::LeafNotFound);
+        }
+
+        self.indexed_proof(idx, leaf)
+    }
+
+    /// Generate a proof of non-membership for the provided value.
+    ///
+    /// The proof will be a membership proof of the low leaf of the value.
+    pub fn non_membership_proof(
+        &mut self,
+        value: &Scalar,
+    ) -> Result<IndexedProof<S::Proof>, Error> {
+        let (idx, leaf) = self.storage.low_leaf(value)?;
+        if &leaf.value == value {
+            return Err(Error::LeafAlreadyExists);
+        }
+
+        self.indexed_proof(idx, leaf)
+    }
+
+    fn indexed_proof(
+        &mut self,
+        idx: usize,
+        leaf: IndexedLeaf,
+    ) -> Result<IndexedProof<S::Proof>, Error> {
+        self.storage
+            .proof(idx)
+            .map(|proof| IndexedProof::new(idx, leaf, proof))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    fn indexed_default() -> IndexedMerkleTree<MemoryIndexedStorage> {
+        IndexedMerkleTree::new(MemoryIndexedStorage::default()).unwrap()
+    }
+
+    #[test]
+    fn indexed_sorted_insert() {
+        let mut t = indexed_default();
+        let values = [30u64, 10, 20];
+
+        for v in values.iter() {
+            t.insert(Scalar::from(*v)).unwrap();
+        }
+
+        let mut leaf = t.leaf(0).unwrap().unwrap();
+        let mut sorted = vec![];
+        while !leaf.is_last() {
+            leaf = t.leaf(leaf.next_index).unwrap().unwrap();
+            sorted.push(leaf.value);
+        }
+
+        assert_eq!(
+            vec![
+                Scalar::from(10u64),
+                Scalar::from(20u64),
+                Scalar::from(30u64)
+            ],
+            sorted
+        );
+        assert!(t.insert(Scalar::from(20u64)).is_err());
+    }
+
+    #[test]
+    fn indexed_membership() {
+        let mut t = indexed_default();
+        for i in 1..10 {
+            t.insert(Scalar::from(i as u64 * 3)).unwrap();
+        }
+
+        let root = t.root().unwrap();
+        let value = Scalar::from(12u64);
+
+        let proof = t.membership_proof(&value).unwrap();
+        assert!(proof.verify_membership(&value, &root));
+        assert!(!proof.verify_membership(&Scalar::from(15u64), &root));
+        assert!(t.membership_proof(&Scalar::from(13u64)).is_err());
+
+        // The proof is bound to the index it carries
+        let moved = IndexedProof::new(proof.index() + 1, *proof.leaf(), *proof.proof());
+        assert!(!moved.verify_membership(&value, &root));
+    }
+
+    #[test]
+    fn indexed_non_membership() {
+        let mut t = indexed_default();
+        for i in 1..10 {
+            t.insert(Scalar::from(i as u64 * 3)).unwrap();
+        }
+
+        let root = t.root().unwrap();
+
+        let value = Scalar::from(13u64);
+        let proof = t.non_membership_proof(&value).unwrap();
+        assert!(proof.verify_non_membership(&value, &root));
+        assert!(!proof.verify_non_membership(&Scalar::from(12u64), &root));
+        assert!(!proof.verify_non_membership(&Scalar::from(16u64), &root));
+
+        // Values greater than the maximum are proven by the last leaf
+        let value = Scalar::from(100u64);
+        let proof = t.non_membership_proof(&value).unwrap();
+        assert!(proof.verify_non_membership(&value, &root));
+
+        assert!(t.non_membership_proof(&Scalar::from(12u64)).is_err());
+    }
+
+    #[cfg(feature = "big-merkle")]
+    #[test]
+    fn indexed_big_storage() {
+        for tree in crate::big_merkle::big_merkle_stores("indexed_big_storage") {
+            let mut t = IndexedMerkleTree::new(BigIndexedStorage::new(tree).unwrap()).unwrap();
+            let mut m = indexed_default();
+
+            for i in 1..10 {
+                let value = Scalar::from(i as u64 * 7 % 10);
+                t.insert(value).unwrap();
+                m.insert(value).unwrap();
+            }
+
+            assert_eq!(m.len(), t.len());
+
+            // The number of leaves is persisted along with them
+            let restored = BigIndexedStorage::new(t.storage().tree().clone()).unwrap();
+            assert_eq!(t.len(), restored.len());
+
+            for i in 0..t.len() {
+                assert_eq!(m.leaf(i).unwrap(), t.leaf(i).unwrap());
+            }
+
+            let root = t.root().unwrap();
+
+            let value = Scalar::from(4u64);
+            let proof = t.membership_proof(&value).unwrap();
+            assert!(proof.verify_membership(&value, &root));
+
+            let value = Scalar::from(11u64);
+            let proof = t.non_membership_proof(&value).unwrap();
+            assert!(proof.verify_non_membership(&value, &root));
+
+            let moved = IndexedProof::new(proof.index() + 1, *proof.leaf(), proof.proof().clone());
+            assert!(!moved.verify_non_membership(&value, &root));
+        }
+    }
+
+    #[cfg(feature = "big-merkle")]
+    #[test]
+    fn indexed_big_storage_rollback() {
+        for tree in crate::big_merkle::big_merkle_stores("indexed_big_storage_rollback") {
+            let mut handle = tree.clone();
+            let id = handle.checkpoint().unwrap();
+
+            let mut t = IndexedMerkleTree::new(BigIndexedStorage::new(tree).unwrap()).unwrap();
+            for i in 1..10 {
+                t.insert(Scalar::from(i as u64 * 3)).unwrap();
+            }
+            let root = t.root().unwrap();
+
+            // The sorted leaves would disagree with the restored hashes
+            match handle.rollback_to(id) {
+                Err(Error::RollbackUnsupported) => (),
+                _ => panic!("The indexed tree was rolled back"),
+            }
+            assert_eq!(root, t.root().unwrap());
+
+            let value = Scalar::from(13u64);
+            let proof = t.non_membership_proof(&value).unwrap();
+            assert!(proof.verify_non_membership(&value, &root));
+            t.insert(value).unwrap();
+        }
+    }
+}
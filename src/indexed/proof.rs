@@ -0,0 +1,72 @@
+use super::IndexedLeaf;
+use crate::{Proof, Scalar};
+
+#[cfg(feature = "big-merkle")]
+use crate::BigProof;
+
+/// Merkle proofs that can authenticate the hash of an [`IndexedLeaf`] against a root.
+pub trait IndexedLeafProof {
+    /// Verify if the provided leaf hash corresponds to the proof in the merkle construction, and
+    /// if the path of the proof leads to the provided leaf index
+    fn verify_leaf(&self, index: usize, leaf: &Scalar, root: &Scalar) -> bool;
+}
+
+impl IndexedLeafProof for Proof<Scalar> {
+    fn verify_leaf(&self, index: usize, leaf: &Scalar, root: &Scalar) -> bool {
+        self.verify_at(index, leaf, root)
+    }
+}
+
+#[cfg(feature = "big-merkle")]
+impl IndexedLeafProof for BigProof<Scalar> {
+    fn verify_leaf(&self, index: usize, leaf: &Scalar, root: &Scalar) -> bool {
+        self.verify_at(index, leaf, root)
+    }
+}
+
+/// Proof of membership or non-membership for an [`IndexedMerkleTree`].
+///
+/// For a membership proof, the leaf holds the provided value. For a non-membership proof, the
+/// leaf is the low leaf of the provided value, which is the leaf with the greatest value that is
+/// smaller than the provided one.
+///
+/// [`IndexedMerkleTree`]: crate::IndexedMerkleTree
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexedProof<P> {
+    index: usize,
+    leaf: IndexedLeaf,
+    proof: P,
+}
+
+impl<P: IndexedLeafProof> IndexedProof<P> {
+    /// IndexedProof constructor
+    pub fn new(index: usize, leaf: IndexedLeaf, proof: P) -> Self {
+        IndexedProof { index, leaf, proof }
+    }
+
+    /// Index of the proven leaf
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// Proven leaf
+    pub fn leaf(&self) -> &IndexedLeaf {
+        &self.leaf
+    }
+
+    /// Merkle proof of the leaf hash
+    pub fn proof(&self) -> &P {
+        &self.proof
+    }
+
+    /// Verify if the provided value is a member of the tree with the provided root
+    pub fn verify_membership(&self, value: &Scalar, root: &Scalar) -> bool {
+        &self.leaf.value == value && self.proof.verify_leaf(self.index, &self.leaf.hash(), root)
+    }
+
+    /// Verify if the provided value is not a member of the tree with the provided root
+    pub fn verify_non_membership(&self, value: &Scalar, root: &Scalar) -> bool {
+        self.leaf.is_low_leaf_of(value)
+            && self.proof.verify_leaf(self.index, &self.leaf.hash(), root)
+    }
+}
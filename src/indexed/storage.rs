@@ -0,0 +1,272 @@
+use super::{IndexedLeaf, IndexedLeafProof};
+use crate::{Error, MerkleTree, Proof, Scalar, MERKLE_WIDTH};
+
+use std::cmp;
+use std::collections::BTreeMap;
+
+#[cfg(feature = "big-merkle")]
+use crate::store::fetch_raw;
+#[cfg(feature = "big-merkle")]
+use crate::{BigMerkleTree, BigProof, IterDirection, StoreBatch};
+
+/// Storage backend of an [`IndexedMerkleTree`].
+///
+/// The backend is responsible for persisting the leaves, maintaining an ordered index of their
+/// values, and hashing them into a merkle tree.
+///
+/// [`IndexedMerkleTree`]: crate::IndexedMerkleTree
+pub trait IndexedStorage {
+    /// Merkle proof generated by the backend
+    type Proof: IndexedLeafProof;
+
+    /// Maximum number of leaves supported by the backend
+    fn capacity(&self) -> usize;
+
+    /// Number of persisted leaves. This is also the next free index
+    fn len(&self) -> usize;
+
+    /// Check if there are no persisted leaves
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Fetch the leaf stored in the provided index
+    fn leaf(&self, idx: usize) -> Result<Option<IndexedLeaf>, Error>;
+
+    /// Fetch the leaf with the greatest value that is smaller than, or equal to, the provided one
+    fn low_leaf(&self, value: &Scalar) -> Result<(usize, IndexedLeaf), Error>;
+
+    /// Persist the provided leaves, replacing any previous leaf of their indexes.
+    ///
+    /// The leaves are persisted atomically, along with the number of leaves, so either all or
+    /// none of them are stored. Every index must be, at most, the number of leaves persisted
+    /// before it.
+    fn put_leaves(&mut self, leaves: &[(usize, IndexedLeaf)]) -> Result<(), Error>;
+
+    /// Calculate and return the root of the merkle tree
+    fn root(&mut self) -> Result<Scalar, Error>;
+
+    /// Generate a proof of membership for the leaf hash stored in the provided index
+    fn proof(&mut self, idx: usize) -> Result<Self::Proof, Error>;
+}
+
+/// Big-endian representation of a scalar, so the lexicographic order is the numeric one.
+fn value_key(value: &Scalar) -> [u8; 32] {
+    let mut key = value.to_bytes();
+    key.reverse();
+    key
+}
+
+/// In-memory storage for an [`IndexedMerkleTree`], backed by a [`MerkleTree`].
+///
+/// [`IndexedMerkleTree`]: crate::IndexedMerkleTree
+#[derive(Clone, Default)]
+pub struct MemoryIndexedStorage {
+    tree: MerkleTree<Scalar>,
+    leaves: Vec<IndexedLeaf>,
+    values: BTreeMap<[u8; 32], usize>,
+}
+
+impl IndexedStorage for MemoryIndexedStorage {
+    type Proof = Proof<Scalar>;
+
+    fn capacity(&self) -> usize {
+        MERKLE_WIDTH
+    }
+
+    fn len(&self) -> usize {
+        self.leaves.len()
+    }
+
+    fn leaf(&self, idx: usize) -> Result<Option<IndexedLeaf>, Error> {
+        Ok(self.leaves.get(idx).copied())
+    }
+
+    fn low_leaf(&self, value: &Scalar) -> Result<(usize, IndexedLeaf), Error> {
+        self.values
+            .range(..=value_key(value))
+            .next_back()
+            .map(|(_, idx)| (*idx, self.leaves[*idx]))
+            .ok_or(Error::LeafNotFound)
+    }
+
+    fn put_leaves(&mut self, leaves: &[(usize, IndexedLeaf)]) -> Result<(), Error> {
+        let mut len = self.leaves.len();
+        for (idx, _) in leaves.iter() {
+            if *idx >= MERKLE_WIDTH || *idx > len {
+                return Err(Error::IndexOutOfBounds);
+            }
+
+            len = cmp::max(len, idx + 1);
+        }
+
+        for (idx, leaf) in leaves.iter() {
+            if *idx == self.leaves.len() {
+                self.leaves.push(*leaf);
+            } else {
+                self.values.remove(&value_key(&self.leaves[*idx].value));
+                self.leaves[*idx] = *leaf;
+            }
+
+            self.values.insert(value_key(&leaf.value), *idx);
+            self.tree.insert_unchecked(*idx, leaf.hash());
+        }
+
+        Ok(())
+    }
+
+    fn root(&mut self) -> Result<Scalar, Error> {
+        Ok(self.tree.root())
+    }
+
+    fn proof(&mut self, idx: usize) -> Result<Self::Proof, Error> {
+        if idx >= self.leaves.len() {
+            return Err(Error::IndexOutOfBounds);
+        }
+
+        Ok(self.tree.proof_index(idx))
+    }
+}
+
+/// Persistent storage for an [`IndexedMerkleTree`], backed by a [`BigMerkleTree`].
+///
+/// The leaves and the ordered index of values are stored in the DB of the big merkle tree, under
+/// keys that cannot collide with the serialized [`MerkleCoord`].
+///
+/// [`IndexedMerkleTree`]: crate::IndexedMerkleTree
+/// [`MerkleCoord`]: crate::MerkleCoord
+#[cfg(feature = "big-merkle")]
+#[derive(Debug)]
+pub struct BigIndexedStorage {
+    tree: BigMerkleTree<Scalar>,
+    len: usize,
+}
+
+#[cfg(feature = "big-merkle")]
+impl BigIndexedStorage {
+    const LEN_KEY: &'static [u8] = b"in";
+    const LEAF_PREFIX: &'static [u8] = b"il";
+    const VALUE_PREFIX: &'static [u8] = b"iv";
+
+    /// BigIndexedStorage constructor.
+    ///
+    /// Will restore the number of leaves previously persisted in the DB of the tree. The leaves
+    /// and the index of values are not restored by a rollback, so the tree can no longer be
+    /// rolled back with [`BigMerkleTree::rollback_to`].
+    pub fn new(tree: BigMerkleTree<Scalar>) -> Result<Self, Error> {
+        tree.flag_external_records()?;
+        let len = fetch_raw(tree.db(), Self::LEN_KEY)?.unwrap_or(0);
+
+        Ok(BigIndexedStorage { tree, len })
+    }
+
+    /// Return a reference to the underlying big merkle tree
+    pub fn tree(&self) -> &BigMerkleTree<Scalar> {
+        &self.tree
+    }
+
+    fn leaf_key(idx: usize) -> Vec<u8> {
+        let mut key = Self::LEAF_PREFIX.to_vec();
+        key.extend_from_slice(&(idx as u64).to_be_bytes());
+        key
+    }
+
+    fn value_key(value: &Scalar) -> Vec<u8> {
+        let mut key = Self::VALUE_PREFIX.to_vec();
+        key.extend_from_slice(&value_key(value));
+        key
+    }
+}
+
+#[cfg(feature = "big-merkle")]
+impl IndexedStorage for BigIndexedStorage {
+    type Proof = BigProof<Scalar>;
+
+    fn capacity(&self) -> usize {
+        self.tree.width()
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn leaf(&self, idx: usize) -> Result<Option<IndexedLeaf>, Error> {
+        fetch_raw(self.tree.db(), Self::leaf_key(idx).as_slice())
+    }
+
+    fn low_leaf(&self, value: &Scalar) -> Result<(usize, IndexedLeaf), Error> {
+        let key = Self::value_key(value);
+
+        let idx = self
+            .tree
+            .db()
+            .iter_from(key.as_slice(), IterDirection::Reverse)
+            .next()
+            .transpose()?
+            .filter(|(k, _)| k.starts_with(Self::VALUE_PREFIX))
+            .ok_or(Error::LeafNotFound)
+            .and_then(|(_, v)| {
+                bincode::deserialize::<usize>(v.as_ref()).map_err(|e| Error::Other(e.to_string()))
+            })?;
+
+        self.leaf(idx)?
+            .map(|leaf| (idx, leaf))
+            .ok_or(Error::LeafNotFound)
+    }
+
+    fn put_leaves(&mut self, leaves: &[(usize, IndexedLeaf)]) -> Result<(), Error> {
+        let mut len = self.len;
+        let mut batch = StoreBatch::default();
+
+        // The leaves of the batch replace the persisted ones
+        let mut written: BTreeMap<usize, IndexedLeaf> = BTreeMap::new();
+        for (idx, leaf) in leaves.iter() {
+            if *idx >= self.tree.width() || *idx > len {
+                return Err(Error::IndexOutOfBounds);
+            }
+
+            let previous = match written.get(idx) {
+                Some(p) => Some(*p),
+                None => self.leaf(*idx)?,
+            };
+            if let Some(p) = previous {
+                batch.delete(Self::value_key(&p.value));
+            }
+
+            let value = bincode::serialize(idx).map_err(|e| Error::Other(e.to_string()))?;
+            batch.put(Self::value_key(&leaf.value), value);
+
+            let bytes = bincode::serialize(leaf).map_err(|e| Error::Other(e.to_string()))?;
+            batch.put(Self::leaf_key(*idx), bytes);
+
+            len = cmp::max(len, idx + 1);
+            written.insert(*idx, *leaf);
+        }
+
+        if len != self.len {
+            let bytes = bincode::serialize(&len).map_err(|e| Error::Other(e.to_string()))?;
+            batch.put(Self::LEN_KEY, bytes);
+        }
+
+        // The hashes are written in the same batch of the leaves
+        self.tree.update_batch_with(
+            batch,
+            leaves.iter().map(|(idx, leaf)| (*idx, Some(leaf.hash()))),
+        )?;
+        self.len = len;
+
+        Ok(())
+    }
+
+    fn root(&mut self) -> Result<Scalar, Error> {
+        self.tree.root()
+    }
+
+    fn proof(&mut self, idx: usize) -> Result<Self::Proof, Error> {
+        if idx >= self.len {
+            return Err(Error::IndexOutOfBounds);
+        }
+
+        self.tree.proof(idx)
+    }
+}
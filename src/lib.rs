@@ -10,20 +10,43 @@ use serde::{Deserialize, Serialize};
 
 pub use crate::poseidon::Poseidon;
 pub use curve25519_dalek::scalar::Scalar;
-pub use error::Error;
-pub use merkle::MerkleTree;
+pub use error::{Error, VerifyError};
+pub use indexed::{
+    IndexedLeaf, IndexedLeafProof, IndexedMerkleTree, IndexedProof, IndexedStorage,
+    MemoryIndexedStorage,
+};
+pub use merkle::{LeafIndexedMerkleTree, MerkleTree};
 pub use proof::Proof;
+pub use root_history::RootHistory;
 
+#[cfg(feature = "async")]
+pub use big_merkle::AsyncBigMerkleTree;
 #[cfg(feature = "big-merkle")]
-pub use big_merkle::{BigMerkleTree, BigProof, MerkleCoord, MerkleRange};
+pub use big_merkle::{
+    BigMerkleTree, BigMerkleTreeBuilder, BigProof, CacheConfig, CacheStats, CheckpointId,
+    ConsistencyProof, MerkleCoord, MerkleRange, Snapshot, TreeOverlay,
+};
+#[cfg(feature = "big-merkle")]
+pub use indexed::BigIndexedStorage;
+#[cfg(feature = "big-merkle")]
+pub use sparse::{SparseMerkleTree, SparseProof};
+#[cfg(feature = "big-merkle")]
+pub use store::{IterDirection, MemoryStore, MerkleStore, StoreBatch, StoreIter};
 
+mod encoding;
 mod error;
+mod indexed;
 mod merkle;
 mod poseidon;
 mod proof;
+mod root_history;
 
 #[cfg(feature = "big-merkle")]
 mod big_merkle;
+#[cfg(feature = "big-merkle")]
+mod sparse;
+#[cfg(feature = "big-merkle")]
+mod store;
 
 include!("constants.rs");
 
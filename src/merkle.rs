@@ -1,6 +1,8 @@
 use crate::{
     Error, Poseidon, PoseidonLeaf, Proof, Scalar, MERKLE_ARITY, MERKLE_HEIGHT, MERKLE_WIDTH,
 };
+use std::collections::{BTreeSet, HashMap};
+use std::hash::Hash;
 use std::ops;
 
 /// The merkle tree will accept up to `MERKLE_ARITY * MERKLE_WIDTH` leaves.
@@ -47,23 +49,16 @@ impl<T: PoseidonLeaf> MerkleTree<T> {
         self.leaves[index].take()
     }
 
-    /// Generate a proof of membership for the provided leaf
+    /// Generate a proof of membership for the lowest index of the provided leaf.
+    ///
+    /// The leaves are scanned; [`LeafIndexedMerkleTree`] maintains an index for this lookup.
     pub fn proof(&mut self, leaf: &T) -> Result<Proof<T>, Error>
     where
         Scalar: ops::Mul<T, Output = T>,
     {
         self.leaves
             .iter()
-            .enumerate()
-            .fold(None, |mut idx, (i, il)| {
-                if let Some(l) = il {
-                    if idx.is_none() && l == leaf {
-                        idx.replace(i);
-                    }
-                }
-
-                idx
-            })
+            .position(|l| l.as_ref() == Some(leaf))
             .ok_or(Error::LeafNotFound)
             .map(|i| self.proof_index(i))
     }
@@ -130,6 +125,144 @@ impl<T: PoseidonLeaf> MerkleTree<T> {
     }
 }
 
+/// [`MerkleTree`] with an index of the present leaves by value, for the proofs of membership of
+/// a leaf without scanning the tree.
+///
+/// The tree is only mutated through the wrapper, so the index is always updated along with the
+/// leaves.
+#[derive(Clone)]
+pub struct LeafIndexedMerkleTree<T: PoseidonLeaf + Eq + Hash> {
+    tree: MerkleTree<T>,
+    /// Indexes of every present leaf
+    index: HashMap<T, BTreeSet<usize>>,
+}
+
+impl<T: PoseidonLeaf + Eq + Hash> Default for LeafIndexedMerkleTree<T> {
+    fn default() -> Self {
+        MerkleTree::default().into()
+    }
+}
+
+impl<T: PoseidonLeaf + Eq + Hash> From<MerkleTree<T>> for LeafIndexedMerkleTree<T> {
+    fn from(tree: MerkleTree<T>) -> Self {
+        let mut index: HashMap<T, BTreeSet<usize>> = HashMap::new();
+        for (i, leaf) in tree.leaves().iter().enumerate() {
+            if let Some(l) = leaf {
+                index.entry(*l).or_default().insert(i);
+            }
+        }
+
+        LeafIndexedMerkleTree { tree, index }
+    }
+}
+
+impl<T: PoseidonLeaf + Eq + Hash> LeafIndexedMerkleTree<T> {
+    /// Return a reference to the underlying merkle tree
+    pub fn tree(&self) -> &MerkleTree<T> {
+        &self.tree
+    }
+
+    /// Insert the provided leaf in the defined position, updating the index of the leaves.
+    ///
+    /// Will fail with [`Error::IndexOutOfBounds`] if `index` is not smaller than the width of the
+    /// tree, leaving both the tree and the index untouched.
+    pub fn insert(&mut self, index: usize, leaf: T) -> Result<(), Error> {
+        if index >= MERKLE_WIDTH {
+            return Err(Error::IndexOutOfBounds);
+        }
+
+        self.insert_unchecked(index, leaf);
+        Ok(())
+    }
+
+    /// Set the provided leaf index as absent for the hash calculation, updating the index of the
+    /// leaves.
+    ///
+    /// Will fail with [`Error::IndexOutOfBounds`] if `index` is not smaller than the width of the
+    /// tree, leaving both the tree and the index untouched.
+    pub fn remove(&mut self, index: usize) -> Result<Option<T>, Error> {
+        if index >= MERKLE_WIDTH {
+            return Err(Error::IndexOutOfBounds);
+        }
+
+        Ok(self.remove_unchecked(index))
+    }
+
+    /// Insert the provided leaf in the defined position.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is out of bounds.
+    pub fn insert_unchecked(&mut self, index: usize, leaf: T) {
+        if let Some(previous) = self.tree.remove_unchecked(index) {
+            self.unindex(&previous, index);
+        }
+
+        self.tree.insert_unchecked(index, leaf);
+        self.index.entry(leaf).or_default().insert(index);
+    }
+
+    /// Set the provided leaf index as absent for the hash calculation.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is out of bounds.
+    pub fn remove_unchecked(&mut self, index: usize) -> Option<T> {
+        let leaf = self.tree.remove_unchecked(index);
+        if let Some(l) = leaf.as_ref() {
+            self.unindex(l, index);
+        }
+
+        leaf
+    }
+
+    fn unindex(&mut self, leaf: &T, index: usize) {
+        let empty = self
+            .index
+            .get_mut(leaf)
+            .map(|indexes| indexes.remove(&index) && indexes.is_empty())
+            .unwrap_or(false);
+
+        if empty {
+            self.index.remove(leaf);
+        }
+    }
+
+    /// Generate a proof of membership for the lowest index of the provided leaf
+    pub fn proof(&mut self, leaf: &T) -> Result<Proof<T>, Error>
+    where
+        Scalar: ops::Mul<T, Output = T>,
+    {
+        let idx = self
+            .index
+            .get(leaf)
+            .and_then(|indexes| indexes.iter().next().copied())
+            .ok_or(Error::LeafNotFound)?;
+
+        Ok(self.tree.proof_index(idx))
+    }
+
+    /// Generate a proof of membership for the provided leaf index
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is out of bounds.
+    pub fn proof_index(&mut self, needle: usize) -> Proof<T>
+    where
+        Scalar: ops::Mul<T, Output = T>,
+    {
+        self.tree.proof_index(needle)
+    }
+
+    /// Calculate and return the root of the merkle tree.
+    pub fn root(&mut self) -> T
+    where
+        Scalar: ops::Mul<T, Output = T>,
+    {
+        self.tree.root()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::*;
@@ -176,6 +309,44 @@ mod tests {
         assert_eq!(t.root(), root)
     }
 
+    #[test]
+    fn merkle_proof_lookup() {
+        let leaf = Scalar::from(7u64);
+        let mut t = LeafIndexedMerkleTree::default();
+        t.insert_unchecked(5, leaf);
+        t.insert_unchecked(2, leaf);
+        t.insert_unchecked(3, Scalar::one());
+
+        // The lowest index of the leaf is proven
+        assert_eq!(2, t.proof(&leaf).unwrap().index());
+        let mut tree = *t.tree();
+        assert_eq!(tree.proof(&leaf).unwrap(), t.proof(&leaf).unwrap());
+        assert_eq!(tree.root(), t.root());
+
+        t.remove_unchecked(2);
+        assert_eq!(5, t.proof(&leaf).unwrap().index());
+
+        t.insert_unchecked(5, Scalar::one());
+        assert!(t.proof(&leaf).is_err());
+        assert_eq!(3, t.proof(&Scalar::one()).unwrap().index());
+
+        // The index is rebuilt from the leaves of the tree
+        let mut t = LeafIndexedMerkleTree::from(*t.tree());
+        assert_eq!(3, t.proof(&Scalar::one()).unwrap().index());
+        assert!(t.proof(&leaf).is_err());
+
+        // The checked mutations fail without touching the tree or the index
+        assert!(t.insert(MERKLE_WIDTH, leaf).is_err());
+        assert!(t.remove(MERKLE_WIDTH).is_err());
+        assert!(t.proof(&leaf).is_err());
+
+        t.insert(1, leaf).unwrap();
+        assert_eq!(1, t.proof(&leaf).unwrap().index());
+        assert_eq!(Some(Scalar::one()), t.remove(3).unwrap());
+        assert_eq!(5, t.proof(&Scalar::one()).unwrap().index());
+        assert_eq!(None, t.remove(3).unwrap());
+    }
+
     #[test]
     fn merkle_sanity_proof() {
         let base = Scalar::one();
@@ -1,4 +1,5 @@
-use crate::{Poseidon, PoseidonLeaf, Scalar, MERKLE_ARITY, MERKLE_HEIGHT};
+use crate::encoding::{decode_levels, encode_levels};
+use crate::{Error, Poseidon, PoseidonLeaf, Scalar, VerifyError, MERKLE_ARITY, MERKLE_HEIGHT};
 use std::ops;
 
 /// Set of pairs (idx, Hash) to reconstruct the merkle root.
@@ -42,24 +43,167 @@ impl<T: PoseidonLeaf> Proof<T> {
         &self.data
     }
 
+    /// Reconstruct the absolute index of the proven leaf from the position of every level
+    pub fn index(&self) -> usize {
+        path_index(self.data.iter().map(|(idx, _)| *idx))
+    }
+
+    /// Verify if the provided leaf corresponds to the proof in the merkle construction, and if
+    /// the path of the proof leads to the provided leaf index.
+    pub fn verify_at(&self, index: usize, leaf: &T, root: &T) -> bool
+    where
+        Scalar: ops::Mul<T, Output = T>,
+    {
+        self.verify_at_detailed(index, leaf, root).is_ok()
+    }
+
     /// Verify if the provided leaf corresponds to the proof in the merkle construction
     pub fn verify(&self, leaf: &T, root: &T) -> bool
     where
         Scalar: ops::Mul<T, Output = T>,
     {
-        let mut leaf = *leaf;
-        let mut h = Poseidon::default();
+        self.verify_detailed(leaf, root).is_ok()
+    }
+
+    /// Same as [`Proof::verify_at`], but reporting the reason of a failure
+    pub fn verify_at_detailed(&self, index: usize, leaf: &T, root: &T) -> Result<(), VerifyError>
+    where
+        Scalar: ops::Mul<T, Output = T>,
+    {
+        self.verify_height()
+            .and_then(|_| verify_levels(&self.data, MERKLE_HEIGHT, Some(index), leaf, root))
+    }
+
+    /// Same as [`Proof::verify`], but reporting the reason of a failure
+    pub fn verify_detailed(&self, leaf: &T, root: &T) -> Result<(), VerifyError>
+    where
+        Scalar: ops::Mul<T, Output = T>,
+    {
+        self.verify_height()
+            .and_then(|_| verify_levels(&self.data, MERKLE_HEIGHT, None, leaf, root))
+    }
+
+    /// Check if every level of the tree was pushed to the proof
+    fn verify_height(&self) -> Result<(), VerifyError> {
+        if self.pos != MERKLE_HEIGHT {
+            return Err(VerifyError::WrongHeight {
+                expected: MERKLE_HEIGHT,
+                found: self.pos,
+            });
+        }
+
+        Ok(())
+    }
+}
+
+/// Verify a path of proof levels, starting from the base of a tree of the provided height.
+///
+/// The path must have one level for every level of the tree, so an inner node cannot be proven
+/// as a leaf. The slot of the path is expected to be either absent, or to hold the same value
+/// that is being proven for that level.
+pub(crate) fn verify_levels<T: PoseidonLeaf>(
+    levels: &[(usize, [Option<T>; MERKLE_ARITY])],
+    height: usize,
+    index: Option<usize>,
+    leaf: &T,
+    root: &T,
+) -> Result<(), VerifyError>
+where
+    Scalar: ops::Mul<T, Output = T>,
+{
+    if levels.len() != height {
+        return Err(VerifyError::WrongHeight {
+            expected: height,
+            found: levels.len(),
+        });
+    }
+
+    if let Some((level, (idx, _))) = levels
+        .iter()
+        .enumerate()
+        .find(|(_, (idx, _))| *idx >= MERKLE_ARITY)
+    {
+        return Err(VerifyError::IndexOutOfRange { level, idx: *idx });
+    }
+
+    if let Some(expected) = index {
+        if !path_matches(levels.iter().map(|(idx, _)| *idx), expected) {
+            return Err(VerifyError::IndexMismatch {
+                expected,
+                found: path_index(levels.iter().map(|(idx, _)| *idx)),
+            });
+        }
+    }
+
+    let mut node = *leaf;
+    let mut h = Poseidon::default();
+
+    for (level, (idx, data)) in levels.iter().enumerate() {
+        match data[*idx] {
+            Some(n) if n != node && level == 0 => return Err(VerifyError::WrongLeaf),
+            Some(n) if n != node => return Err(VerifyError::BadSibling { level: level - 1 }),
+            _ => (),
+        }
+
+        h.replace(&data[0..MERKLE_ARITY]);
+        h.insert_unchecked(*idx, node);
+
+        node = h.hash();
+    }
+
+    if &node != root {
+        return Err(VerifyError::RootMismatch);
+    }
 
-        for i in 0..self.data.len() {
-            let (idx, data) = self.data[i];
+    Ok(())
+}
 
-            h.replace(&data[0..MERKLE_ARITY]);
-            h.insert_unchecked(idx, leaf);
+/// Reconstruct a leaf index from the positions of a path, starting from the base of the tree
+pub(crate) fn path_index<I: DoubleEndedIterator<Item = usize>>(path: I) -> usize {
+    path.rev().fold(0, |index, idx| {
+        index.saturating_mul(MERKLE_ARITY).saturating_add(idx)
+    })
+}
 
-            leaf = h.hash();
+/// Check if the positions of a path, starting from the base of the tree, lead to the provided
+/// leaf index.
+///
+/// Every position must be within the arity, and the index must be within the width defined by
+/// the length of the path.
+pub(crate) fn path_matches<I: Iterator<Item = usize>>(path: I, mut index: usize) -> bool {
+    for idx in path {
+        if idx >= MERKLE_ARITY || idx != index % MERKLE_ARITY {
+            return false;
         }
 
-        &leaf == root
+        index /= MERKLE_ARITY;
+    }
+
+    index == 0
+}
+
+impl Proof<Scalar> {
+    /// Serialize the proof into its canonical wire format.
+    ///
+    /// For every level, the position index is followed by a bitmap of the present leaves, and
+    /// only the present leaves are encoded.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        encode_levels(MERKLE_HEIGHT, &self.data)
+    }
+
+    /// Deserialize a proof from its canonical wire format.
+    ///
+    /// Will fail if the height or the number of levels is not the height of the tree, or if the
+    /// provided bytes are not a canonical encoding.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        let levels = decode_levels(bytes, MERKLE_HEIGHT)?;
+
+        let mut proof = Proof::default();
+        levels
+            .iter()
+            .for_each(|(idx, leaves)| proof.push(*idx, leaves));
+
+        Ok(proof)
     }
 }
 
@@ -94,4 +238,150 @@ mod tests {
         let proof = t.proof_index(i + 1);
         assert!(!proof.verify(&Scalar::from(i as u64), &root));
     }
+
+    #[test]
+    fn proof_verify_at() {
+        let mut t = MerkleTree::<Scalar>::default();
+        for i in 0..MERKLE_WIDTH {
+            t.insert_unchecked(i, Scalar::from((i % 2) as u64));
+        }
+
+        let root = t.root();
+        let i = MERKLE_WIDTH / 3;
+
+        let proof = t.proof_index(i);
+        assert_eq!(i, proof.index());
+
+        let leaf = Scalar::from((i % 2) as u64);
+        assert!(proof.verify_at(i, &leaf, &root));
+
+        // The same leaf is also present in other positions of the tree
+        assert!(!proof.verify_at(i + 2, &leaf, &root));
+        assert!(!proof.verify_at(i + MERKLE_WIDTH, &leaf, &root));
+    }
+
+    #[test]
+    fn proof_verify_detailed() {
+        let mut t = MerkleTree::<Scalar>::default();
+        for i in 0..MERKLE_WIDTH {
+            t.insert_unchecked(i, Scalar::from(i as u64));
+        }
+
+        let root = t.root();
+        let i = MERKLE_WIDTH / 3;
+        let leaf = Scalar::from(i as u64);
+
+        let proof = t.proof_index(i);
+        assert_eq!(Ok(()), proof.verify_detailed(&leaf, &root));
+        assert_eq!(
+            Err(VerifyError::WrongLeaf),
+            proof.verify_detailed(&Scalar::zero(), &root)
+        );
+        assert_eq!(
+            Err(VerifyError::RootMismatch),
+            proof.verify_detailed(&leaf, &Scalar::zero())
+        );
+        assert_eq!(
+            Err(VerifyError::IndexMismatch {
+                expected: i + 1,
+                found: i
+            }),
+            proof.verify_at_detailed(i + 1, &leaf, &root)
+        );
+
+        let mut tampered = proof;
+        let sibling = (tampered.data[0].0 + 1) % MERKLE_ARITY;
+        tampered.data[0].1[sibling] = Some(Scalar::zero());
+        assert_eq!(
+            Err(VerifyError::BadSibling { level: 0 }),
+            tampered.verify_detailed(&leaf, &root)
+        );
+
+        let mut tampered = proof;
+        tampered.data[1].0 = MERKLE_ARITY;
+        assert_eq!(
+            Err(VerifyError::IndexOutOfRange {
+                level: 1,
+                idx: MERKLE_ARITY
+            }),
+            tampered.verify_detailed(&leaf, &root)
+        );
+
+        assert_eq!(
+            Err(VerifyError::WrongHeight {
+                expected: MERKLE_HEIGHT,
+                found: 0
+            }),
+            Proof::default().verify_detailed(&leaf, &root)
+        );
+    }
+
+    #[test]
+    fn proof_bytes() {
+        let mut t = MerkleTree::<Scalar>::default();
+        for i in 0..MERKLE_WIDTH / 2 {
+            t.insert_unchecked(i, Scalar::from(i as u64));
+        }
+
+        let root = t.root();
+        let i = MERKLE_WIDTH / 3;
+
+        let proof = t.proof_index(i);
+        let bytes = proof.to_bytes();
+        let decoded = Proof::from_bytes(bytes.as_slice()).unwrap();
+
+        assert_eq!(proof, decoded);
+        assert_eq!(bytes, decoded.to_bytes());
+        assert!(decoded.verify(&Scalar::from(i as u64), &root));
+    }
+
+    #[test]
+    fn proof_bytes_malformed() {
+        let mut t = MerkleTree::<Scalar>::default();
+        t.insert_unchecked(0, Scalar::one());
+
+        let bytes = t.proof_index(0).to_bytes();
+
+        // Truncated and trailing bytes
+        assert!(Proof::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert!(Proof::from_bytes(trailing.as_slice()).is_err());
+
+        // Unknown version
+        let mut version = bytes.clone();
+        version[0] = 0xff;
+        assert!(Proof::from_bytes(version.as_slice()).is_err());
+
+        // Height encoded in a longer form than the shortest varint
+        let mut varint = bytes.clone();
+        varint[1] |= 0x80;
+        varint.insert(2, 0);
+        assert!(Proof::from_bytes(varint.as_slice()).is_err());
+
+        // Height of another tree
+        let mut height = bytes.clone();
+        height[1] += 1;
+        assert!(Proof::from_bytes(height.as_slice()).is_err());
+
+        // Number of levels that is not the height of the tree
+        let mut levels = t.proof_index(0).data()[..MERKLE_HEIGHT - 1].to_vec();
+        let short = crate::encoding::encode_levels(MERKLE_HEIGHT, levels.as_slice());
+        assert!(Proof::from_bytes(short.as_slice()).is_err());
+        levels.push(levels[0]);
+        levels.push(levels[0]);
+        let long = crate::encoding::encode_levels(MERKLE_HEIGHT, levels.as_slice());
+        assert!(Proof::from_bytes(long.as_slice()).is_err());
+
+        // Position out of the arity
+        let mut idx = bytes.clone();
+        idx[3] = MERKLE_ARITY as u8;
+        assert!(Proof::from_bytes(idx.as_slice()).is_err());
+
+        // Non-canonical scalar, with the first leaf set to the maximum 256-bit integer
+        let mut scalar = bytes.clone();
+        let offset = 4 + (MERKLE_ARITY + 7) / 8;
+        scalar[offset..offset + 32].copy_from_slice(&[0xff; 32]);
+        assert!(Proof::from_bytes(scalar.as_slice()).is_err());
+    }
 }
@@ -0,0 +1,156 @@
+use crate::{Error, PoseidonLeaf};
+
+use std::collections::VecDeque;
+
+#[cfg(feature = "big-merkle")]
+use serde::{Deserialize, Serialize};
+
+/// Bounded ring buffer of the roots of a merkle tree, keyed by a caller-supplied epoch.
+///
+/// Proofs are usually generated against a root that is a few epochs old. The history allows
+/// validators to accept proofs against any of the last `capacity` recorded roots.
+///
+/// # Example
+/// ```
+/// use dusk_poseidon_merkle::*;
+///
+/// let mut t = MerkleTree::default();
+/// let mut history = RootHistory::new(2);
+///
+/// t.insert_unchecked(0, Scalar::one());
+/// let root = t.root();
+/// history.record(1, root).unwrap();
+///
+/// t.insert_unchecked(1, Scalar::one());
+/// history.record(2, t.root()).unwrap();
+///
+/// assert!(history.is_known_root(&root));
+/// assert_eq!(Some(1), history.epoch_of(&root));
+/// ```
+#[cfg_attr(feature = "big-merkle", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "big-merkle", serde(bound = ""))]
+#[derive(Debug, Clone, PartialEq)]
+pub struct RootHistory<T: PoseidonLeaf> {
+    capacity: usize,
+    roots: VecDeque<(u64, T)>,
+}
+
+impl<T: PoseidonLeaf> RootHistory<T> {
+    /// RootHistory constructor. The capacity is the maximum number of retained roots.
+    pub fn new(capacity: usize) -> Self {
+        RootHistory {
+            capacity,
+            roots: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    /// Maximum number of retained roots
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Change the maximum number of retained roots, discarding the oldest ones if required
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        self.truncate();
+    }
+
+    /// Number of retained roots
+    pub fn len(&self) -> usize {
+        self.roots.len()
+    }
+
+    /// Check if there are no retained roots
+    pub fn is_empty(&self) -> bool {
+        self.roots.is_empty()
+    }
+
+    /// Iterate over the retained pairs of (epoch, root), from the oldest to the latest
+    pub fn iter(&self) -> impl Iterator<Item = &(u64, T)> {
+        self.roots.iter()
+    }
+
+    /// Latest recorded pair of (epoch, root)
+    pub fn latest(&self) -> Option<(u64, T)> {
+        self.roots.back().copied()
+    }
+
+    /// Record the root of the tree for the provided epoch.
+    ///
+    /// Recording the last epoch again will replace its root. Epochs older than the last recorded
+    /// one are rejected.
+    pub fn record(&mut self, epoch: u64, root: T) -> Result<(), Error> {
+        match self.roots.back_mut() {
+            Some((e, _)) if *e > epoch => {
+                return Err(Error::Other(format!(
+                    "The epoch {} is older than the last recorded epoch {}.",
+                    epoch, e
+                )))
+            }
+            Some((e, r)) if *e == epoch => *r = root,
+            _ => self.roots.push_back((epoch, root)),
+        }
+
+        self.truncate();
+
+        Ok(())
+    }
+
+    /// Check if the provided root is one of the retained roots
+    pub fn is_known_root(&self, root: &T) -> bool {
+        self.epoch_of(root).is_some()
+    }
+
+    /// Latest epoch in which the provided root was current
+    pub fn epoch_of(&self, root: &T) -> Option<u64> {
+        self.roots
+            .iter()
+            .rev()
+            .find(|(_, r)| r == root)
+            .map(|(e, _)| *e)
+    }
+
+    /// Root that was current in the provided epoch, if the epoch is retained
+    pub fn root_at(&self, epoch: u64) -> Option<T> {
+        self.roots
+            .iter()
+            .rev()
+            .find(|(e, _)| *e <= epoch)
+            .map(|(_, r)| *r)
+    }
+
+    fn truncate(&mut self) {
+        while self.roots.len() > self.capacity {
+            self.roots.pop_front();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn root_history_ring() {
+        let mut history = RootHistory::new(3);
+        for i in 1..6u64 {
+            history.record(i * 10, Scalar::from(i)).unwrap();
+        }
+
+        assert_eq!(3, history.len());
+        assert!(!history.is_known_root(&Scalar::from(2u64)));
+        assert!(history.is_known_root(&Scalar::from(3u64)));
+        assert_eq!(Some(50), history.epoch_of(&Scalar::from(5u64)));
+
+        // The root of an epoch is current until the next recorded epoch
+        assert_eq!(Some(Scalar::from(4u64)), history.root_at(45));
+        assert_eq!(None, history.root_at(25));
+
+        assert!(history.record(40, Scalar::zero()).is_err());
+        history.record(50, Scalar::zero()).unwrap();
+        assert_eq!(Some((50, Scalar::zero())), history.latest());
+
+        history.set_capacity(1);
+        assert_eq!(1, history.len());
+    }
+}
@@ -0,0 +1,454 @@
+use crate::store::fetch_raw;
+use crate::{
+    Error, MerkleCoord, MerkleStore, Poseidon, PoseidonLeaf, Scalar, StoreBatch, MERKLE_ARITY,
+};
+
+use std::ops;
+use std::path::Path;
+use std::sync::{Arc, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use rocksdb::DB;
+use serde::Deserialize;
+
+pub use proof::SparseProof;
+
+mod proof;
+
+/// Number of bits of the key consumed by every level of the tree
+pub(crate) const SPARSE_BITS: usize = MERKLE_ARITY.trailing_zeros() as usize;
+
+/// Number of levels required to consume all the 256 bits of a key
+pub(crate) const SPARSE_DEPTH: usize = (256 + SPARSE_BITS - 1) / SPARSE_BITS;
+
+/// Representation of a node of the sparse tree.
+///
+/// The level `0` represents the leaves, and the path is the key shifted right by the bits
+/// consumed up to the level. The root is on the level [`SPARSE_DEPTH`] with a zeroed path.
+///
+/// The coordinates are persisted as the keys of a [`MerkleCoord`], with the big-endian path in
+/// place of the index, so the nodes of a level share the prefix of the level.
+#[derive(PartialEq, Debug, Copy, Clone)]
+struct SparseCoord {
+    level: usize,
+    path: [u8; 32],
+}
+
+impl SparseCoord {
+    fn new(key: &[u8; 32], level: usize) -> Self {
+        SparseCoord {
+            level,
+            path: shr(key, level * SPARSE_BITS),
+        }
+    }
+
+    /// Length of the encoded key of a coordinate
+    const KEY_LEN: usize = 40;
+
+    /// Encode the coordinate as a store key
+    fn to_key(self) -> [u8; SparseCoord::KEY_LEN] {
+        let mut key = [0u8; SparseCoord::KEY_LEN];
+        key[..8].copy_from_slice(&MerkleCoord::level_prefix(self.level));
+
+        // The path is little-endian
+        for (k, p) in key[8..].iter_mut().zip(self.path.iter().rev()) {
+            *k = *p;
+        }
+
+        key
+    }
+
+    /// Attempt to fetch a node from a store
+    fn fetch_node<T>(self, db: &dyn MerkleStore) -> Result<Option<T>, Error>
+    where
+        T: for<'a> Deserialize<'a>,
+    {
+        fetch_raw(db, &self.to_key())
+    }
+
+    /// Position of the node inside its parent
+    fn slot(&self) -> usize {
+        self.path[0] as usize & (MERKLE_ARITY - 1)
+    }
+
+    /// Coordinate of the node occupying the provided position inside the same parent
+    fn sibling(&self, slot: usize) -> Self {
+        let mut path = self.path;
+        path[0] = (path[0] & !((MERKLE_ARITY - 1) as u8)) | slot as u8;
+
+        SparseCoord {
+            level: self.level,
+            path,
+        }
+    }
+}
+
+/// Shift a little-endian 256-bit integer right by the provided number of bits
+fn shr(key: &[u8; 32], n: usize) -> [u8; 32] {
+    let mut r = [0u8; 32];
+    let bytes = n / 8;
+    let bits = n % 8;
+
+    for (i, b) in r.iter_mut().enumerate() {
+        let j = i + bytes;
+
+        if j < 32 {
+            *b = key[j] >> bits;
+
+            if bits > 0 && j + 1 < 32 {
+                *b |= key[j + 1] << (8 - bits);
+            }
+        }
+    }
+
+    r
+}
+
+/// Position of the provided key inside the node of the provided level
+pub(crate) fn key_slot(key: &Scalar, level: usize) -> usize {
+    SparseCoord::new(key.as_bytes(), level).slot()
+}
+
+/// Authenticated key-value map, where the path of a leaf is defined by the bits of its key.
+///
+/// Every level of the tree consumes `log2(MERKLE_ARITY)` bits of the key, starting from the least
+/// significant ones. Only the nodes that differ from the empty sub-tree of their level are
+/// persisted.
+///
+/// The arity of the tree must be a power of two.
+///
+/// The clones of a tree are handles to the same tree: the updates of every handle are
+/// serialized, and the reads never observe a partial update.
+#[derive(Debug)]
+pub struct SparseMerkleTree<T: PoseidonLeaf> {
+    db: Arc<dyn MerkleStore>,
+    defaults: Vec<Option<T>>,
+    /// Held for the write by the updates, and for the read by the reads of several nodes
+    lock: Arc<RwLock<()>>,
+}
+
+impl<T: PoseidonLeaf> Clone for SparseMerkleTree<T> {
+    fn clone(&self) -> Self {
+        SparseMerkleTree {
+            db: Arc::clone(&self.db),
+            defaults: self.defaults.clone(),
+            lock: Arc::clone(&self.lock),
+        }
+    }
+}
+
+impl<T: PoseidonLeaf> SparseMerkleTree<T> {
+    /// `SparseMerkleTree` constructor, persisting the nodes in RocksDB
+    pub fn new<D: AsRef<Path>>(db_path: D) -> Result<Self, Error>
+    where
+        Scalar: ops::Mul<T, Output = T>,
+    {
+        let db = DB::open_default(db_path).map_err(|e| Error::Other(e.to_string()))?;
+
+        Self::with_store(Arc::new(db))
+    }
+
+    /// `SparseMerkleTree` constructor for the provided storage backend
+    pub fn with_store(db: Arc<dyn MerkleStore>) -> Result<Self, Error>
+    where
+        Scalar: ops::Mul<T, Output = T>,
+    {
+        if !MERKLE_ARITY.is_power_of_two() {
+            return Err(Error::Other(
+                "The arity of a sparse merkle tree must be a power of two.".to_owned(),
+            ));
+        }
+
+        // The empty leaf is absent. Every empty node above is the hash of its empty children
+        let mut defaults = Vec::with_capacity(SPARSE_DEPTH + 1);
+        let mut h = Poseidon::default();
+
+        defaults.push(None);
+        for level in 0..SPARSE_DEPTH {
+            h.replace(&[defaults[level]; MERKLE_ARITY]);
+            defaults.push(Some(h.hash()));
+        }
+
+        Ok(SparseMerkleTree {
+            db,
+            defaults,
+            lock: Arc::new(RwLock::new(())),
+        })
+    }
+
+    /// Lock the tree for a read, waiting for the current update to finish
+    fn read(&self) -> RwLockReadGuard<'_, ()> {
+        // The updates are written in a single batch, so a poisoned lock is usable
+        self.lock.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Lock the tree for an update, waiting for the current readers to finish
+    fn write(&self) -> RwLockWriteGuard<'_, ()> {
+        self.lock.write().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Return a reference to the internal path of the DB, if persisted in the file system
+    pub fn db_path(&self) -> Option<&Path> {
+        self.db.path()
+    }
+
+    /// Number of levels of the tree
+    pub fn depth(&self) -> usize {
+        SPARSE_DEPTH
+    }
+
+    /// Hash of the empty sub-tree for the provided level
+    pub fn default_node(&self, level: usize) -> Option<T> {
+        self.defaults[level]
+    }
+
+    fn node(&self, coord: SparseCoord) -> Result<Option<T>, Error> {
+        coord
+            .fetch_node(self.db.as_ref())
+            .map(|n| n.or(self.defaults[coord.level]))
+    }
+
+    /// Fetch the leaf stored under the provided key
+    pub fn get(&self, key: &Scalar) -> Result<Option<T>, Error> {
+        SparseCoord::new(key.as_bytes(), 0).fetch_node(self.db.as_ref())
+    }
+
+    /// Insert the provided leaf under the provided key
+    pub fn insert(&mut self, key: &Scalar, leaf: T) -> Result<(), Error>
+    where
+        Scalar: ops::Mul<T, Output = T>,
+    {
+        self.update(key, Some(leaf))
+    }
+
+    /// Remove the leaf stored under the provided key
+    pub fn remove(&mut self, key: &Scalar) -> Result<(), Error>
+    where
+        Scalar: ops::Mul<T, Output = T>,
+    {
+        self.update(key, None)
+    }
+
+    /// Replace the leaf of the provided key, and recalculate the path up to the root.
+    ///
+    /// The whole path is written in a single batch, so the root always matches its sub-tree. The
+    /// siblings are read and the batch is written under the write lock, so the updates of the
+    /// other handles cannot interleave.
+    fn update(&mut self, key: &Scalar, leaf: Option<T>) -> Result<(), Error>
+    where
+        Scalar: ops::Mul<T, Output = T>,
+    {
+        let _lock = self.write();
+
+        let key = key.as_bytes();
+        let mut node = leaf;
+        let mut h = Poseidon::default();
+        let mut batch = StoreBatch::default();
+
+        for level in 0..SPARSE_DEPTH + 1 {
+            let coord = SparseCoord::new(key, level);
+            let c = coord.to_key();
+
+            // Empty nodes are not persisted, so the tree remains sparse
+            if node == self.defaults[level] {
+                batch.delete(&c[..]);
+            } else if let Some(n) = node {
+                let n = bincode::serialize(&n).map_err(|e| Error::Other(e.to_string()))?;
+                batch.put(&c[..], n);
+            }
+
+            if level == SPARSE_DEPTH {
+                break;
+            }
+
+            let mut children = [None; MERKLE_ARITY];
+            for (i, c) in children.iter_mut().enumerate() {
+                *c = if i == coord.slot() {
+                    node
+                } else {
+                    self.node(coord.sibling(i))?
+                };
+            }
+
+            h.replace(&children);
+            node = Some(h.hash());
+        }
+
+        // The siblings are never on the path, so they are not affected by the pending batch
+        self.db.write(batch)
+    }
+
+    /// Return the root of the merkle tree.
+    pub fn root(&self) -> Result<T, Error> {
+        self.node(SparseCoord::new(&[0u8; 32], SPARSE_DEPTH))
+            .and_then(|n| {
+                n.ok_or(Error::Other(
+                    "It was not possible to obtain the root node from the merkle tree.".to_owned(),
+                ))
+            })
+    }
+
+    /// Generate a proof for the provided key.
+    ///
+    /// The proof can be used for membership, if a leaf is stored under the key, or
+    /// non-membership otherwise.
+    pub fn proof(&self, key: &Scalar) -> Result<SparseProof<T>, Error> {
+        let _lock = self.read();
+        let mut proof = SparseProof::new(*key);
+
+        for level in 0..SPARSE_DEPTH {
+            let coord = SparseCoord::new(key.as_bytes(), level);
+            let mut siblings = [None; MERKLE_ARITY];
+
+            for (i, s) in siblings.iter_mut().enumerate() {
+                *s = self.node(coord.sibling(i))?;
+            }
+
+            proof.push(siblings);
+        }
+
+        Ok(proof)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use std::sync::Arc;
+    use std::thread;
+    use tempdir::TempDir;
+
+    fn sparse_stores(path: &str) -> Vec<SparseMerkleTree<Scalar>> {
+        let db_path = TempDir::new(path).map(|t| t.into_path()).unwrap();
+
+        vec![
+            SparseMerkleTree::new(db_path).unwrap(),
+            SparseMerkleTree::with_store(Arc::new(MemoryStore::default())).unwrap(),
+        ]
+    }
+
+    #[test]
+    fn sparse_insert_remove() {
+        for mut t in sparse_stores("sparse_insert_remove") {
+            let empty = t.root().unwrap();
+            let key = -Scalar::one();
+
+            assert!(t.get(&key).unwrap().is_none());
+
+            t.insert(&key, Scalar::from(5u64)).unwrap();
+            assert_eq!(Some(Scalar::from(5u64)), t.get(&key).unwrap());
+            assert_ne!(empty, t.root().unwrap());
+
+            // The leaf is persisted under the prefix of its level, followed by the big-endian key
+            let prefix = MerkleCoord::level_prefix(0);
+            let leaves: Vec<_> = t.db.iter_prefix(&prefix).map(|i| i.unwrap().0).collect();
+            let mut be = key.as_bytes().to_vec();
+            be.reverse();
+            assert_eq!(1, leaves.len());
+            assert_eq!(&be[..], &leaves[0][8..]);
+
+            t.remove(&key).unwrap();
+            assert!(t.get(&key).unwrap().is_none());
+            assert_eq!(empty, t.root().unwrap());
+        }
+    }
+
+    #[test]
+    fn sparse_order_independent() {
+        let t1 = sparse_stores("sparse_order_independent_1");
+        let t2 = sparse_stores("sparse_order_independent_2");
+
+        for (mut t1, mut t2) in t1.into_iter().zip(t2) {
+            for i in 0..8u64 {
+                t1.insert(&Scalar::from(i * 31), Scalar::from(i)).unwrap();
+                t2.insert(&Scalar::from((7 - i) * 31), Scalar::from(7 - i))
+                    .unwrap();
+            }
+
+            assert_eq!(t1.root().unwrap(), t2.root().unwrap());
+        }
+    }
+
+    #[test]
+    fn sparse_concurrent_updates() {
+        for t in sparse_stores("sparse_concurrent_updates") {
+            // The keys share the upper levels of their paths
+            let writers: Vec<_> = (0..4u64)
+                .map(|w| {
+                    let mut t = t.clone();
+
+                    thread::spawn(move || {
+                        for i in 0..4 {
+                            let key = Scalar::from(w * 4 + i);
+                            t.insert(&key, key).unwrap();
+                        }
+                    })
+                })
+                .collect();
+            writers.into_iter().for_each(|w| w.join().unwrap());
+
+            let mut reference =
+                SparseMerkleTree::with_store(Arc::new(MemoryStore::default())).unwrap();
+            for i in 0..16u64 {
+                reference.insert(&Scalar::from(i), Scalar::from(i)).unwrap();
+            }
+            assert_eq!(reference.root().unwrap(), t.root().unwrap());
+
+            let proof = t.proof(&Scalar::from(9u64)).unwrap();
+            assert!(proof.verify(&Scalar::from(9u64), &t.root().unwrap()));
+        }
+    }
+
+    #[test]
+    fn sparse_proof() {
+        for mut t in sparse_stores("sparse_proof") {
+            for i in 1..6u64 {
+                t.insert(&Scalar::from(i * 1_000_003), Scalar::from(i))
+                    .unwrap();
+            }
+
+            let root = t.root().unwrap();
+
+            let key = Scalar::from(3_000_009u64);
+            let proof = t.proof(&key).unwrap();
+            assert!(proof.verify(&Scalar::from(3u64), &root));
+            assert!(!proof.verify(&Scalar::from(4u64), &root));
+            assert!(!proof.verify_absent(&root));
+
+            let key = Scalar::from(3_000_010u64);
+            let proof = t.proof(&key).unwrap();
+            assert!(proof.verify_absent(&root));
+            assert!(!proof.verify(&Scalar::from(3u64), &root));
+
+            // A proof that stops below the root cannot prove the inner nodes as leaves
+            let key = Scalar::from(3_000_009u64);
+            let mut leaves = t.proof(&key).unwrap().data()[0];
+            leaves[super::key_slot(&key, 0)] = Some(Scalar::from(3u64));
+            let mut h = Poseidon::default();
+            h.replace(&leaves);
+
+            let mut short = SparseProof::new(key);
+            short.push(leaves);
+            assert!(!short.verify(&Scalar::from(3u64), &h.hash()));
+        }
+    }
+
+    #[test]
+    fn sparse_persistence() {
+        let db_path = TempDir::new("sparse_persistence")
+            .map(|t| t.into_path())
+            .unwrap();
+
+        let root = {
+            let mut t = SparseMerkleTree::new(&db_path).unwrap();
+            t.insert(&Scalar::from(7u64), Scalar::from(11u64)).unwrap();
+            t.root().unwrap()
+        };
+
+        let t = SparseMerkleTree::<Scalar>::new(&db_path).unwrap();
+        assert_eq!(root, t.root().unwrap());
+        assert_eq!(
+            Some(Scalar::from(11u64)),
+            t.get(&Scalar::from(7u64)).unwrap()
+        );
+    }
+}
@@ -0,0 +1,77 @@
+use super::{key_slot, SPARSE_DEPTH};
+use crate::{Poseidon, PoseidonLeaf, Scalar, MERKLE_ARITY};
+
+use std::ops;
+
+/// Proof of membership or non-membership for a key of a [`SparseMerkleTree`].
+///
+/// For every level of the tree, there is the set of nodes that share the parent with the path of
+/// the key. The position of the path inside every set is defined by the bits of the key.
+///
+/// [`SparseMerkleTree`]: crate::SparseMerkleTree
+#[derive(Debug, Clone, PartialEq)]
+pub struct SparseProof<T: PoseidonLeaf> {
+    key: Scalar,
+    data: Vec<[Option<T>; MERKLE_ARITY]>,
+}
+
+impl<T: PoseidonLeaf> SparseProof<T> {
+    pub(crate) fn new(key: Scalar) -> Self {
+        SparseProof { key, data: vec![] }
+    }
+
+    pub(crate) fn push(&mut self, leaves: [Option<T>; MERKLE_ARITY]) {
+        self.data.push(leaves)
+    }
+
+    /// Key of the proven leaf
+    pub fn key(&self) -> &Scalar {
+        &self.key
+    }
+
+    /// Return the raw proof data
+    pub fn data(&self) -> &Vec<[Option<T>; MERKLE_ARITY]> {
+        &self.data
+    }
+
+    /// Verify if the provided leaf is stored under the key of the proof
+    pub fn verify(&self, leaf: &T, root: &T) -> bool
+    where
+        Scalar: ops::Mul<T, Output = T>,
+    {
+        self.root_for(Some(*leaf)).as_ref() == Some(root)
+    }
+
+    /// Verify if there is no leaf stored under the key of the proof
+    pub fn verify_absent(&self, root: &T) -> bool
+    where
+        Scalar: ops::Mul<T, Output = T>,
+    {
+        self.root_for(None).as_ref() == Some(root)
+    }
+
+    /// Calculate the root of the tree, if the provided leaf is stored under the key.
+    ///
+    /// A proof that does not reach the root has no root.
+    fn root_for(&self, leaf: Option<T>) -> Option<T>
+    where
+        Scalar: ops::Mul<T, Output = T>,
+    {
+        if self.data.len() != SPARSE_DEPTH {
+            return None;
+        }
+
+        let mut node = leaf;
+        let mut h = Poseidon::default();
+
+        for (level, data) in self.data.iter().enumerate() {
+            let mut leaves = *data;
+            leaves[key_slot(&self.key, level)] = node;
+
+            h.replace(&leaves);
+            node = Some(h.hash());
+        }
+
+        node
+    }
+}
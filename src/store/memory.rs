@@ -0,0 +1,178 @@
+use super::{IterDirection, MerkleStore, StoreBatch, StoreIter};
+use crate::Error;
+
+use std::collections::{BTreeMap, VecDeque};
+use std::ops::Bound;
+use std::sync::RwLock;
+
+/// Volatile [`MerkleStore`] backed by a `BTreeMap`.
+///
+/// Mostly useful for tests, and for trees that do not need to outlive the process.
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    map: RwLock<BTreeMap<Vec<u8>, Vec<u8>>>,
+}
+
+impl MerkleStore for MemoryStore {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+        self.map
+            .read()
+            .map(|m| m.get(key).cloned())
+            .map_err(|e| Error::Other(e.to_string()))
+    }
+
+    fn put(&self, key: &[u8], value: &[u8]) -> Result<(), Error> {
+        self.map
+            .write()
+            .map(|mut m| {
+                m.insert(key.to_vec(), value.to_vec());
+            })
+            .map_err(|e| Error::Other(e.to_string()))
+    }
+
+    fn delete(&self, key: &[u8]) -> Result<(), Error> {
+        self.map
+            .write()
+            .map(|mut m| {
+                m.remove(key);
+            })
+            .map_err(|e| Error::Other(e.to_string()))
+    }
+
+    fn write(&self, batch: StoreBatch) -> Result<(), Error> {
+        let mut map = self.map.write().map_err(|e| Error::Other(e.to_string()))?;
+
+        for (key, value) in batch.ops {
+            match value {
+                Some(v) => map.insert(key, v),
+                None => map.remove(&key),
+            };
+        }
+
+        Ok(())
+    }
+
+    fn iter_from<'a>(&'a self, key: &[u8], direction: IterDirection) -> StoreIter<'a> {
+        Box::new(MemoryIter {
+            store: self,
+            direction,
+            from: Some(Bound::Included(key.to_vec())),
+            items: VecDeque::new(),
+        })
+    }
+}
+
+/// Lazy iterator over a [`MemoryStore`].
+///
+/// The lock of the map cannot outlive a call, so the items are read in bounded chunks, each
+/// starting after the last key of the previous one. The chunks are read under different locks, so
+/// the iteration observes the writes performed between them.
+struct MemoryIter<'a> {
+    store: &'a MemoryStore,
+    direction: IterDirection,
+    /// Bound of the next chunk, or `None` if the iteration is over
+    from: Option<Bound<Vec<u8>>>,
+    items: VecDeque<(Vec<u8>, Vec<u8>)>,
+}
+
+impl<'a> MemoryIter<'a> {
+    /// Maximum number of items read under a single lock
+    const CHUNK: usize = 256;
+
+    fn fill(&mut self) -> Result<(), Error> {
+        let from = match self.from.take() {
+            Some(f) => f,
+            None => return Ok(()),
+        };
+
+        let map = self
+            .store
+            .map
+            .read()
+            .map_err(|e| Error::Other(e.to_string()))?;
+
+        let range = match self.direction {
+            IterDirection::Forward => map.range((from, Bound::Unbounded)),
+            IterDirection::Reverse => map.range((Bound::Unbounded, from)),
+        };
+        let chunk = |(k, v): (&Vec<u8>, &Vec<u8>)| (k.clone(), v.clone());
+        self.items = match self.direction {
+            IterDirection::Forward => range.take(Self::CHUNK).map(chunk).collect(),
+            IterDirection::Reverse => range.rev().take(Self::CHUNK).map(chunk).collect(),
+        };
+
+        if self.items.len() == Self::CHUNK {
+            self.from = self.items.back().map(|(k, _)| Bound::Excluded(k.clone()));
+        }
+
+        Ok(())
+    }
+}
+
+impl<'a> Iterator for MemoryIter<'a> {
+    type Item = Result<(Vec<u8>, Vec<u8>), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.items.is_empty() {
+            if let Err(e) = self.fill() {
+                return Some(Err(e));
+            }
+        }
+
+        self.items.pop_front().map(Ok)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MemoryIter, MemoryStore};
+    use crate::*;
+
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn memory_store_iter_chunks() {
+        let store = MemoryStore::default();
+        let len = MemoryIter::CHUNK * 2 + 1;
+        for i in 0..len as u16 {
+            store.put(&i.to_be_bytes(), &[]).unwrap();
+        }
+
+        let keys: Vec<Vec<u8>> = store
+            .iter_from(&[0, 1], IterDirection::Forward)
+            .map(|i| i.unwrap().0)
+            .collect();
+        let expected: Vec<Vec<u8>> = (1..len as u16).map(|i| i.to_be_bytes().to_vec()).collect();
+        assert_eq!(expected, keys);
+
+        let keys: Vec<Vec<u8>> = store
+            .iter_from(&[0xff], IterDirection::Reverse)
+            .map(|i| i.unwrap().0)
+            .collect();
+        let expected: Vec<Vec<u8>> = (0..len as u16)
+            .rev()
+            .map(|i| i.to_be_bytes().to_vec())
+            .collect();
+        assert_eq!(expected, keys);
+    }
+
+    #[test]
+    fn memory_store_iter_poisoned() {
+        let store = Arc::new(MemoryStore::default());
+        store.put(b"a", b"1").unwrap();
+
+        let poisoned = Arc::clone(&store);
+        thread::spawn(move || {
+            let _map = poisoned.map.write().unwrap();
+            panic!("Poison the lock of the store");
+        })
+        .join()
+        .unwrap_err();
+
+        // The failure is reported instead of an empty store
+        let mut iter = store.iter_prefix(b"a");
+        assert!(iter.next().unwrap().is_err());
+        assert!(iter.next().is_none());
+    }
+}
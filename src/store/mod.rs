@@ -0,0 +1,164 @@
+use crate::Error;
+
+use std::fmt;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+pub use memory::MemoryStore;
+
+mod memory;
+mod rocks;
+
+/// Direction of an iteration over the keys of a [`MerkleStore`]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum IterDirection {
+    /// Ascending order of keys
+    Forward,
+    /// Descending order of keys
+    Reverse,
+}
+
+/// Iterator over the pairs of (key, value) of a [`MerkleStore`]. A failure of the backend is
+/// yielded as an error, and ends the iteration.
+pub type StoreIter<'a> = Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>), Error>> + 'a>;
+
+/// Set of operations to be atomically applied to a [`MerkleStore`]
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct StoreBatch {
+    ops: Vec<(Vec<u8>, Option<Vec<u8>>)>,
+}
+
+impl StoreBatch {
+    /// Set the provided value for the key
+    pub fn put<K: AsRef<[u8]>, V: AsRef<[u8]>>(&mut self, key: K, value: V) {
+        self.ops
+            .push((key.as_ref().to_vec(), Some(value.as_ref().to_vec())));
+    }
+
+    /// Remove the provided key
+    pub fn delete<K: AsRef<[u8]>>(&mut self, key: K) {
+        self.ops.push((key.as_ref().to_vec(), None));
+    }
+
+    /// Number of operations of the batch
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    /// Check if there are no operations in the batch
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    /// Operations of the batch, in insertion order. An absent value represents a deletion.
+    pub fn ops(&self) -> &[(Vec<u8>, Option<Vec<u8>>)] {
+        self.ops.as_slice()
+    }
+}
+
+/// Ordered key-value storage backend for the persistent merkle trees.
+///
+/// Every method takes a shared reference, so the backend is responsible for its own
+/// synchronization, and the same store can be shared between clones of a tree.
+pub trait MerkleStore: fmt::Debug + Send + Sync {
+    /// Fetch the value of the provided key
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, Error>;
+
+    /// Set the value of the provided key
+    fn put(&self, key: &[u8], value: &[u8]) -> Result<(), Error>;
+
+    /// Remove the provided key
+    fn delete(&self, key: &[u8]) -> Result<(), Error>;
+
+    /// Atomically apply all the operations of the batch
+    fn write(&self, batch: StoreBatch) -> Result<(), Error>;
+
+    /// Iterate over the pairs of (key, value), starting from the provided key, in the provided
+    /// direction.
+    ///
+    /// The first element is the provided key, if present, or the next one in the direction of
+    /// the iteration.
+    fn iter_from<'a>(&'a self, key: &[u8], direction: IterDirection) -> StoreIter<'a>;
+
+    /// Iterate over the pairs of (key, value) that start with the provided prefix, in ascending
+    /// order.
+    fn iter_prefix<'a>(&'a self, prefix: &'a [u8]) -> StoreIter<'a> {
+        Box::new(self.iter_from(prefix, IterDirection::Forward).take_while(
+            move |item| match item {
+                Ok((k, _)) => k.starts_with(prefix),
+                Err(_) => true,
+            },
+        ))
+    }
+
+    /// Path of the underlying storage, if persisted in the file system
+    fn path(&self) -> Option<&Path> {
+        None
+    }
+}
+
+/// Attempt to fetch an item from a store, for a raw key
+pub(crate) fn fetch_raw<T>(db: &dyn MerkleStore, key: &[u8]) -> Result<Option<T>, Error>
+where
+    T: for<'a> Deserialize<'a>,
+{
+    db.get(key)?
+        .map(|b| bincode::deserialize::<T>(b.as_ref()).map_err(|e| Error::Other(e.to_string())))
+        .transpose()
+}
+
+/// Attempt to persist an item into a store, for a raw key
+pub(crate) fn persist_raw<T>(db: &dyn MerkleStore, key: &[u8], item: T) -> Result<(), Error>
+where
+    T: Serialize,
+{
+    let item = bincode::serialize(&item).map_err(|e| Error::Other(e.to_string()))?;
+
+    db.put(key, item.as_slice())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use tempdir::TempDir;
+
+    fn stores(path: &str) -> Vec<Box<dyn MerkleStore>> {
+        let db_path = TempDir::new(path).map(|t| t.into_path()).unwrap();
+        let db = rocksdb::DB::open_default(db_path).unwrap();
+
+        vec![Box::new(db), Box::new(MemoryStore::default())]
+    }
+
+    #[test]
+    fn store_operations() {
+        for store in stores("store_operations") {
+            store.put(b"b", b"2").unwrap();
+            store.put(b"d", b"4").unwrap();
+            assert_eq!(Some(b"2".to_vec()), store.get(b"b").unwrap());
+
+            let mut batch = StoreBatch::default();
+            batch.put(b"a", b"1");
+            batch.put(b"c", b"3");
+            batch.delete(b"d");
+            store.write(batch).unwrap();
+            assert!(store.get(b"d").unwrap().is_none());
+
+            let keys: Vec<Vec<u8>> = store
+                .iter_from(b"b", IterDirection::Forward)
+                .map(|i| i.unwrap().0)
+                .collect();
+            assert_eq!(vec![b"b".to_vec(), b"c".to_vec()], keys);
+
+            let keys: Vec<Vec<u8>> = store
+                .iter_from(b"bb", IterDirection::Reverse)
+                .map(|i| i.unwrap().0)
+                .collect();
+            assert_eq!(vec![b"b".to_vec(), b"a".to_vec()], keys);
+
+            store.delete(b"a").unwrap();
+            assert_eq!(1, store.iter_prefix(b"c").count());
+            assert_eq!(0, store.iter_prefix(b"a").count());
+        }
+    }
+}
@@ -0,0 +1,78 @@
+use super::{IterDirection, MerkleStore, StoreBatch, StoreIter};
+use crate::Error;
+
+use std::path::Path;
+
+use rocksdb::{DBIterator, Direction, IteratorMode, WriteBatch, DB};
+
+impl MerkleStore for DB {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+        DB::get(self, key).map_err(|e| Error::Other(e.to_string()))
+    }
+
+    fn put(&self, key: &[u8], value: &[u8]) -> Result<(), Error> {
+        DB::put(self, key, value).map_err(|e| Error::Other(e.to_string()))
+    }
+
+    fn delete(&self, key: &[u8]) -> Result<(), Error> {
+        DB::delete(self, key).map_err(|e| Error::Other(e.to_string()))
+    }
+
+    fn write(&self, batch: StoreBatch) -> Result<(), Error> {
+        let mut b = WriteBatch::default();
+
+        for (key, value) in batch.ops {
+            match value {
+                Some(v) => b.put(key, v),
+                None => b.delete(key),
+            }
+        }
+
+        DB::write(self, b).map_err(|e| Error::Other(e.to_string()))
+    }
+
+    fn iter_from<'a>(&'a self, key: &[u8], direction: IterDirection) -> StoreIter<'a> {
+        let direction = match direction {
+            IterDirection::Forward => Direction::Forward,
+            IterDirection::Reverse => Direction::Reverse,
+        };
+
+        Box::new(RocksIter {
+            iter: self.iterator(IteratorMode::From(key, direction)),
+            done: false,
+        })
+    }
+
+    fn path(&self) -> Option<&Path> {
+        Some(DB::path(self))
+    }
+}
+
+/// Iterator over a RocksDB, yielding the failure of the underlying iterator, if any, as its last
+/// item
+struct RocksIter<'a> {
+    iter: DBIterator<'a>,
+    done: bool,
+}
+
+impl<'a> Iterator for RocksIter<'a> {
+    type Item = Result<(Vec<u8>, Vec<u8>), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        match self.iter.next() {
+            Some((k, v)) => Some(Ok((k.into_vec(), v.into_vec()))),
+            None => {
+                // The iterator ends on a failure, which is reported by its status
+                self.done = true;
+                self.iter
+                    .status()
+                    .err()
+                    .map(|e| Err(Error::Other(e.to_string())))
+            }
+        }
+    }
+}